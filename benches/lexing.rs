@@ -1,17 +1,43 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 use gobo_rust::lex;
 use gobo_rust::source_text::SourceText;
 use std::hint::black_box;
 
+// Lines dominated by indentation, block comments and long strings, where the
+// vectorised scanners do most of the work. To measure the scanners alone, run this
+// once with `--features scalar-scanners` and then without it, so that Criterion
+// compares the two. `--features no-simd` also turns off the older SIMD searches for
+// line breaks and identifiers.
+fn make_scanner_heavy_source() -> String {
+    let mut source = String::new();
+    for i in 0..2000 {
+        source.push_str("\t\t\t\t/* a block comment that runs for a while before it ends */\n");
+        source.push_str(&format!(
+            "\t\t\t\tvar message_{i} = \"a string literal with an \\\"escape\\\" and some padding\";\n"
+        ));
+        source.push_str("                        \n");
+    }
+    source
+}
+
 pub fn bench_lex(c: &mut Criterion) {
     static TEST_SOURCE: &str = include_str!("large_file.gml");
-    let text = SourceText::from_str(TEST_SOURCE);
+    let scanner_heavy_source = make_scanner_heavy_source();
 
-    c.bench_function("bench_lex", |b| {
-        b.iter(|| {
-            let result = lex::lex(black_box(&text));
+    let mut group = c.benchmark_group("lex");
+    for (name, source) in [
+        ("large_file", TEST_SOURCE),
+        ("scanner_heavy", scanner_heavy_source.as_str()),
+    ] {
+        let text = SourceText::from_str(source);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                black_box(lex::lex(black_box(&text)));
+            });
         });
-    });
+    }
+    group.finish();
 }
 
 criterion_group!(benches, bench_lex);
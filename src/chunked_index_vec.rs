@@ -3,6 +3,7 @@ use std::alloc::{self, Layout};
 use std::marker::PhantomData;
 use std::mem;
 use std::mem::MaybeUninit;
+use std::ops::Range;
 use std::ptr::NonNull;
 
 pub struct ChunkedIndexVec<T, I: TypedIndex> {
@@ -54,10 +55,76 @@ impl<T, I: TypedIndex> ChunkedIndexVec<T, I> {
         self.chunks.resize_with(final_chunk_index + 1, Chunk::new);
     }
 
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let (chunk_index, pos) = Self::get_chunk_and_index(len);
+        self.chunks.truncate(chunk_index + 1);
+        self.chunks[chunk_index].truncate(pos);
+        self.len = len;
+    }
+
+    /// Removes the elements from `at` on and returns them.
+    pub fn split_off(&mut self, at: usize) -> Vec<T>
+    where
+        T: Copy,
+    {
+        let tail = (at..self.len)
+            .map(|index| *self.get(I::from(index)))
+            .collect();
+        self.truncate(at);
+        tail
+    }
+
+    /// Replaces the elements in `range` with `replace_with`. The elements after `range`
+    /// are moved in place to follow the new ones, and `update` is called on each of them.
+    pub fn splice(
+        &mut self,
+        range: Range<usize>,
+        replace_with: &[T],
+        mut update: impl FnMut(&mut T),
+    ) where
+        T: Copy,
+    {
+        debug_assert!(range.start <= range.end && range.end <= self.len);
+        let old_len = self.len;
+        let tail_start = range.start + replace_with.len();
+
+        if tail_start > range.end {
+            // make room at the end, then move the tail back to front
+            let grow = tail_start - range.end;
+            for _ in 0..grow {
+                self.push(replace_with[0]);
+            }
+            for index in (range.end..old_len).rev() {
+                let mut value = *self.get(I::from(index));
+                update(&mut value);
+                *self.get_mut(I::from(index + grow)) = value;
+            }
+        } else {
+            let shrink = range.end - tail_start;
+            for index in range.end..old_len {
+                let mut value = *self.get(I::from(index));
+                update(&mut value);
+                *self.get_mut(I::from(index - shrink)) = value;
+            }
+            self.truncate(old_len - shrink);
+        }
+
+        for (offset, &value) in replace_with.iter().enumerate() {
+            *self.get_mut(I::from(range.start + offset)) = value;
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (I, &T)> {
         Iter {
             inner: self,
@@ -73,6 +140,12 @@ impl<T, I: TypedIndex> ChunkedIndexVec<T, I> {
     }
 }
 
+impl<T, I: TypedIndex> Default for ChunkedIndexVec<T, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// An allocated chunk of `T` with fixed capacity.
 struct Chunk<T> {
     ptr: NonNull<MaybeUninit<T>>,
@@ -131,6 +204,16 @@ impl<T> Chunk<T> {
         self.len += 1;
     }
 
+    pub fn truncate(&mut self, len: usize) {
+        debug_assert!(len <= self.len);
+        if mem::needs_drop::<T>() {
+            for i in len..self.len {
+                unsafe { self.ptr.as_ptr().add(i).cast::<T>().drop_in_place() };
+            }
+        }
+        self.len = len;
+    }
+
     pub fn get(&self, index: usize) -> &T {
         debug_assert!(index < self.len);
         unsafe { &*self.ptr.as_ptr().add(index).cast::<T>() }
@@ -140,10 +223,6 @@ impl<T> Chunk<T> {
         debug_assert!(index < self.len);
         unsafe { &mut *self.ptr.as_ptr().add(index).cast::<T>() }
     }
-
-    pub fn len(&self) -> usize {
-        self.len
-    }
 }
 
 impl<T> Drop for Chunk<T> {
@@ -1,10 +1,16 @@
+mod doc_comment;
+mod feather;
 mod identifier_lexer;
 mod lexer;
+mod line_map;
 mod number_lexer;
+mod string_lexer;
 pub mod token;
 mod tokenized_text;
-mod string_lexer;
 
+pub use doc_comment::*;
+pub use feather::*;
 pub use lexer::*;
-pub use tokenized_text::*;
+pub use line_map::*;
 pub use token::*;
+pub use tokenized_text::*;
@@ -0,0 +1,325 @@
+use crate::lex::identifier_lexer::{is_identifier_start, scan_identifier};
+use crate::lex::token::TokenIndex;
+use crate::source_text::{SourceText, TextRange, TextSize};
+
+/// A run of `///` comment lines, parsed into a description and tags.
+#[derive(Debug, Clone)]
+pub struct DocBlock {
+    pub range: TextRange,
+    /// The first token after the block, e.g. the `function` keyword or `#macro`
+    /// directive that the block documents.
+    pub target: TokenIndex,
+    /// The text before the first tag.
+    pub description: String,
+    pub tags: Vec<DocTag>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocTagKind {
+    /// `@function name` or `@func name`
+    Function,
+    /// `@param {Type} name description`, or one of its aliases
+    Param,
+    /// `@returns {Type} description` or `@return {Type} description`
+    Returns,
+    /// `@description text` or `@desc text`
+    Description,
+    /// Any other tag, e.g. `@self` or `@deprecated`
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct DocTag {
+    pub kind: DocTagKind,
+    /// The tag name, including the `@`.
+    pub tag: TextRange,
+    /// The type expression between the braces, without the braces.
+    pub type_expr: Option<TextRange>,
+    /// The documented name, for `@param` and `@function`.
+    pub name: Option<TextRange>,
+    /// Whether a `@param` name was written in square brackets, e.g. `[count]`.
+    pub is_optional: bool,
+    /// The rest of the tag, including any lines that follow it without a tag of their own.
+    pub description: String,
+}
+
+impl DocTagKind {
+    fn from_name(name: &[u8]) -> DocTagKind {
+        match name {
+            b"function" | b"func" => DocTagKind::Function,
+            b"param" | b"parameter" | b"arg" | b"argument" => DocTagKind::Param,
+            b"returns" | b"return" => DocTagKind::Returns,
+            b"description" | b"desc" => DocTagKind::Description,
+            _ => DocTagKind::Other,
+        }
+    }
+}
+
+/// Parses the `///` lines in `lines`, given as the range of each comment.
+pub(crate) fn parse_doc_block(
+    text: &SourceText,
+    lines: &[TextRange],
+    target: TokenIndex,
+) -> DocBlock {
+    debug_assert!(!lines.is_empty());
+
+    let mut block = DocBlock {
+        range: TextRange::new(lines[0].start(), lines[lines.len() - 1].end()),
+        target,
+        description: String::new(),
+        tags: Vec::new(),
+    };
+
+    for line in lines {
+        let mut parser = DocLineParser {
+            text,
+            cursor: line.start() + 3,
+            end: line.end(),
+        };
+        parser.skip_whitespace();
+
+        if parser.current() == b'@' {
+            block.tags.push(parser.parse_tag());
+            continue;
+        }
+
+        let content = parser.rest();
+        let description = match block.tags.last_mut() {
+            Some(tag) => &mut tag.description,
+            None => &mut block.description,
+        };
+        append_line(description, &content);
+    }
+
+    block
+}
+
+fn append_line(description: &mut String, line: &str) {
+    if !description.is_empty() {
+        description.push('\n');
+    }
+    description.push_str(line);
+}
+
+struct DocLineParser<'a> {
+    text: &'a SourceText<'a>,
+    cursor: TextSize,
+    end: TextSize,
+}
+
+impl DocLineParser<'_> {
+    fn current(&self) -> u8 {
+        if self.cursor < self.end {
+            self.text.get_byte(self.cursor)
+        } else {
+            0
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.current(), b' ' | b'\t') {
+            self.cursor += 1;
+        }
+    }
+
+    fn scan_name(&mut self) -> Option<TextRange> {
+        if !is_identifier_start(self.current()) {
+            return None;
+        }
+        let start = self.cursor;
+        let len = scan_identifier(self.text.get_slice(start..self.end));
+        self.cursor += len;
+        Some(TextRange::new(start, self.cursor))
+    }
+
+    fn rest(&mut self) -> String {
+        let rest = self.text.get_slice(self.cursor..self.end);
+        self.cursor = self.end;
+        String::from_utf8_lossy(rest).trim_end().to_owned()
+    }
+
+    fn parse_tag(&mut self) -> DocTag {
+        debug_assert!(self.current() == b'@');
+        let tag_start = self.cursor;
+        self.cursor += 1;
+        let kind = match self.scan_name() {
+            Some(name) => DocTagKind::from_name(self.text.get_slice(name.start()..name.end())),
+            None => DocTagKind::Other,
+        };
+
+        let mut tag = DocTag {
+            kind,
+            tag: TextRange::new(tag_start, self.cursor),
+            type_expr: None,
+            name: None,
+            is_optional: false,
+            description: String::new(),
+        };
+        self.skip_whitespace();
+
+        if matches!(kind, DocTagKind::Param | DocTagKind::Returns) && self.current() == b'{' {
+            tag.type_expr = Some(self.parse_type_expr());
+            self.skip_whitespace();
+        }
+
+        match kind {
+            DocTagKind::Function => {
+                tag.name = self.scan_name();
+            }
+            DocTagKind::Param => {
+                if self.current() == b'[' {
+                    self.cursor += 1;
+                    self.skip_whitespace();
+                    tag.name = self.scan_name();
+                    tag.is_optional = true;
+
+                    // skip the default value, e.g. `[count=1]`
+                    while self.cursor < self.end && self.current() != b']' {
+                        self.cursor += 1;
+                    }
+                    if self.current() == b']' {
+                        self.cursor += 1;
+                    }
+                } else {
+                    tag.name = self.scan_name();
+                }
+            }
+            _ => {}
+        }
+
+        self.skip_whitespace();
+        if self.current() == b'-' {
+            self.cursor += 1;
+            self.skip_whitespace();
+        }
+        tag.description = self.rest();
+        tag
+    }
+
+    // Type expressions can contain braces of their own, e.g. `{Struct.{x: Real}}`. An
+    // unclosed type runs to the end of the line.
+    fn parse_type_expr(&mut self) -> TextRange {
+        debug_assert!(self.current() == b'{');
+        self.cursor += 1;
+        let start = self.cursor;
+        let mut depth = 1;
+
+        while self.cursor < self.end {
+            match self.current() {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let range = TextRange::new(start, self.cursor);
+                        self.cursor += 1;
+                        return range;
+                    }
+                }
+                _ => {}
+            }
+            self.cursor += 1;
+        }
+
+        TextRange::new(start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::{TokenKind, lex};
+
+    fn slice(text: &str, range: Option<TextRange>) -> Option<&str> {
+        range.map(|range| &text[usize::from(range.start())..usize::from(range.end())])
+    }
+
+    #[test]
+    fn param_tags() {
+        let text = "\
+/// Moves the player.
+/// @param {Real} speed - how far to move
+/// @param {Struct.{x: Real, y: Real}} [target=undefined] where to go,
+///   and what to do there
+/// @returns {Bool}
+function move(speed, target) {}
+";
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        let blocks = tokens.doc_blocks(&source);
+        assert_eq!(blocks.len(), 1);
+
+        let block = &blocks[0];
+        assert_eq!(block.description, "Moves the player.");
+        assert_eq!(tokens.get_kind(block.target), TokenKind::Function);
+
+        let tags: Vec<_> = block
+            .tags
+            .iter()
+            .map(|tag| {
+                (
+                    tag.kind,
+                    slice(text, tag.type_expr),
+                    slice(text, tag.name),
+                    tag.is_optional,
+                    tag.description.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                (
+                    DocTagKind::Param,
+                    Some("Real"),
+                    Some("speed"),
+                    false,
+                    "how far to move"
+                ),
+                (
+                    DocTagKind::Param,
+                    Some("Struct.{x: Real, y: Real}"),
+                    Some("target"),
+                    true,
+                    "where to go,\nand what to do there"
+                ),
+                (DocTagKind::Returns, Some("Bool"), None, false, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn quadruple_slash_is_not_a_doc_comment() {
+        let text = "//// Section\nfunction f() {}\n";
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        assert!(tokens.doc_blocks(&source).is_empty());
+    }
+
+    // a block followed by a blank line doesn't document the next declaration
+    #[test]
+    fn block_before_blank_line_is_skipped() {
+        let text = "/// Not about f\n\nfunction f() {}\n/// About g\nfunction g() {}\n";
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        let blocks = tokens.doc_blocks(&source);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].description, "About g");
+        assert_eq!(tokens.get_kind(blocks[0].target), TokenKind::Function);
+        assert_eq!(
+            usize::from(tokens.get_start(blocks[0].target)),
+            text.find("function g").unwrap()
+        );
+
+        let f = (0..tokens.token_count())
+            .map(TokenIndex::from)
+            .find(|&token| tokens.get_kind(token) == TokenKind::Function)
+            .unwrap();
+        assert!(tokens.doc_block(&source, f).is_none());
+        assert_eq!(
+            tokens
+                .doc_block(&source, blocks[0].target)
+                .map(|block| block.description),
+            Some("About g".to_owned())
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use crate::lex::{LineIndex, TokenKind, TokenizedText};
+use crate::parse::ParseDiagnostic;
+use crate::source_text::{SourceText, TextRange, TextSize};
+use std::fmt::{Debug, Display, Formatter};
+
+// The manual's "Feather Messages" page lists Feather's messages in two series: GM1xxx for
+// type checking and syntax errors, and GM2xxx for style. New messages are added with most
+// releases, so rather than keep a copy of the list, any id in either series is accepted.
+const KNOWN_SERIES: [u16; 2] = [1, 2];
+
+/// The id of a Feather message, e.g. `GM2017`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RuleId(u16);
+
+impl RuleId {
+    pub const fn new(number: u16) -> Self {
+        Self(number)
+    }
+
+    pub fn number(self) -> u16 {
+        self.0
+    }
+
+    /// Parses `GM` followed by four digits.
+    pub fn parse(name: &[u8]) -> Option<RuleId> {
+        let digits = name.strip_prefix(b"GM")?;
+        if digits.len() != 4 || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let number = digits
+            .iter()
+            .fold(0, |number, &digit| number * 10 + u16::from(digit - b'0'));
+        Some(RuleId(number))
+    }
+
+    /// Whether the id is in one of Feather's message series.
+    pub fn is_known(self) -> bool {
+        KNOWN_SERIES.contains(&(self.0 / 1000))
+    }
+}
+
+impl Display for RuleId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GM{:04}", self.0)
+    }
+}
+
+impl Debug for RuleId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// A range of lines where a Feather rule is turned off by a comment directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suppression {
+    /// The suppressed rule, or `None` for `all`.
+    pub rule: Option<RuleId>,
+    /// The first suppressed line.
+    pub start_line: LineIndex,
+    /// The line after the last suppressed one.
+    pub end_line: LineIndex,
+}
+
+impl Suppression {
+    pub fn applies_to(&self, rule: RuleId, line: LineIndex) -> bool {
+        self.rule.is_none_or(|suppressed| suppressed == rule)
+            && self.start_line <= line
+            && line < self.end_line
+    }
+}
+
+const UNKNOWN_RULE: &str = "unknown Feather rule id";
+const MISSING_RULE: &str = "expected a Feather rule id";
+
+// suppression errors are reported again whenever the text is edited
+pub(crate) fn is_suppression_error(message: &str) -> bool {
+    matches!(message, UNKNOWN_RULE | MISSING_RULE)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// `disable` or `ignore`: from the directive's line to the end of the file
+    Disable,
+    /// `disable once` or `ignore once`: only the line after the directive
+    DisableOnce,
+    /// `enable` or `restore`: ends an earlier `disable`
+    Enable,
+}
+
+/// Reads the `// Feather disable GM2017` style directives in the comments of `tokens`.
+/// Each directive names one or more rule ids, or `all`. Other Feather directives, such as
+/// `// Feather use syntax-errors`, are left alone.
+pub(crate) fn read_suppressions(
+    tokens: &TokenizedText,
+    text: &SourceText,
+) -> (Vec<Suppression>, Vec<ParseDiagnostic>) {
+    let mut reader = SuppressionReader {
+        suppressions: Vec::new(),
+        diagnostics: Vec::new(),
+        open: Vec::new(),
+    };
+
+    for (_, comment) in tokens.comments() {
+        let trivia = tokens.get_trivia(comment.trivia());
+        if trivia.kind() != TokenKind::SingleLineComment {
+            continue;
+        }
+        let mut parser = DirectiveParser {
+            text,
+            cursor: trivia.start() + 2,
+            end: trivia.end(),
+        };
+        if let Some(action) = parser.parse_action() {
+            let line = tokens.find_line_index(trivia.start());
+            reader.read_directive(&mut parser, action, line);
+        }
+    }
+
+    let end_line = LineIndex::from(tokens.lines.len());
+    for (rule, start_line) in std::mem::take(&mut reader.open) {
+        reader.add(rule, start_line, end_line);
+    }
+    reader
+        .suppressions
+        .sort_by_key(|suppression| suppression.start_line);
+    (reader.suppressions, reader.diagnostics)
+}
+
+struct SuppressionReader {
+    suppressions: Vec<Suppression>,
+    diagnostics: Vec<ParseDiagnostic>,
+    // rules turned off by `disable`, with the line of the directive
+    open: Vec<(Option<RuleId>, LineIndex)>,
+}
+
+impl SuppressionReader {
+    fn read_directive(&mut self, parser: &mut DirectiveParser, action: Action, line: LineIndex) {
+        let mut has_rule = false;
+        while let Some(word) = parser.next_word() {
+            has_rule = true;
+            let name = parser.text.get_slice(word.start()..word.end());
+            let rule = if name == b"all" {
+                None
+            } else {
+                match RuleId::parse(name) {
+                    Some(rule) if rule.is_known() => Some(rule),
+                    _ => {
+                        self.report(UNKNOWN_RULE, word);
+                        continue;
+                    }
+                }
+            };
+            self.apply(action, rule, line);
+        }
+
+        if !has_rule {
+            self.report(MISSING_RULE, TextRange::new(parser.end, parser.end));
+        }
+    }
+
+    fn apply(&mut self, action: Action, rule: Option<RuleId>, line: LineIndex) {
+        match action {
+            Action::Disable => {
+                if !self.open.iter().any(|&(open_rule, _)| open_rule == rule) {
+                    self.open.push((rule, line));
+                }
+            }
+            Action::DisableOnce => self.add(rule, line + 1, line + 2),
+            // `enable all` ends every rule that was turned off
+            Action::Enable => {
+                let mut open = std::mem::take(&mut self.open);
+                open.retain(|&(open_rule, start_line)| {
+                    let is_enabled = rule.is_none() || open_rule == rule;
+                    if is_enabled {
+                        self.add(open_rule, start_line, line);
+                    }
+                    !is_enabled
+                });
+                self.open = open;
+            }
+        }
+    }
+
+    fn add(&mut self, rule: Option<RuleId>, start_line: LineIndex, end_line: LineIndex) {
+        if start_line < end_line {
+            self.suppressions.push(Suppression {
+                rule,
+                start_line,
+                end_line,
+            });
+        }
+    }
+
+    fn report(&mut self, message: &'static str, range: TextRange) {
+        self.diagnostics
+            .push(ParseDiagnostic::new(message, range.start(), range.end()));
+    }
+}
+
+struct DirectiveParser<'a> {
+    text: &'a SourceText<'a>,
+    cursor: TextSize,
+    end: TextSize,
+}
+
+impl DirectiveParser<'_> {
+    fn current(&self) -> u8 {
+        if self.cursor < self.end {
+            self.text.get_byte(self.cursor)
+        } else {
+            0
+        }
+    }
+
+    // words are separated by whitespace, and rule ids may also be separated by commas
+    fn next_word(&mut self) -> Option<TextRange> {
+        while matches!(self.current(), b' ' | b'\t' | b',') {
+            self.cursor += 1;
+        }
+        let start = self.cursor;
+        while self.cursor < self.end && !matches!(self.current(), b' ' | b'\t' | b',' | b'\r') {
+            self.cursor += 1;
+        }
+        (self.cursor > start).then(|| TextRange::new(start, self.cursor))
+    }
+
+    fn next_word_is(&mut self, expected: &[u8]) -> bool {
+        let start = self.cursor;
+        match self.next_word() {
+            Some(word) if self.text.get_slice(word.start()..word.end()) == expected => true,
+            _ => {
+                self.cursor = start;
+                false
+            }
+        }
+    }
+
+    fn parse_action(&mut self) -> Option<Action> {
+        if !self.next_word_is(b"Feather") {
+            return None;
+        }
+        let word = self.next_word()?;
+        match self.text.get_slice(word.start()..word.end()) {
+            b"disable" | b"ignore" if self.next_word_is(b"once") => Some(Action::DisableOnce),
+            b"disable" | b"ignore" => Some(Action::Disable),
+            b"enable" | b"restore" => Some(Action::Enable),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::lex;
+    use crate::lex::token::TokenIndex;
+
+    // the one-letter identifiers in `text` where `rule` is suppressed
+    fn suppressed(text: &str, rule: &str) -> Vec<char> {
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        assert_eq!(messages(&tokens), [], "{text:?}");
+        let rule = RuleId::parse(rule.as_bytes()).unwrap();
+        (0..tokens.token_count())
+            .map(TokenIndex::from)
+            .filter(|&token| {
+                tokens.get_kind(token) == TokenKind::Identifier && tokens.is_suppressed(rule, token)
+            })
+            .map(|token| char::from(text.as_bytes()[usize::from(tokens.get_start(token))]))
+            .collect()
+    }
+
+    fn messages(tokens: &TokenizedText) -> Vec<(&'static str, TextRange)> {
+        tokens
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.message, diagnostic.range))
+            .collect()
+    }
+
+    fn range(start: usize, end: usize) -> TextRange {
+        TextRange::new(start.into(), end.into())
+    }
+
+    #[test]
+    fn known_rules() {
+        for name in ["GM1041", "GM1100", "GM2017", "GM1999", "GM2999"] {
+            assert!(RuleId::parse(name.as_bytes()).unwrap().is_known(), "{name}");
+        }
+        for name in ["GM0000", "GM0999", "GM3000", "GM9999"] {
+            assert!(
+                !RuleId::parse(name.as_bytes()).unwrap().is_known(),
+                "{name}"
+            );
+        }
+        for name in ["GM201", "GM20170", "gm2017", "GMabcd", "2017"] {
+            assert_eq!(RuleId::parse(name.as_bytes()), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn disable_until_enable() {
+        let text = "\
+a
+// Feather disable GM2017
+b
+c
+// Feather enable GM2017
+d
+// Feather disable GM2017
+e
+";
+        assert_eq!(suppressed(text, "GM2017"), ['b', 'c', 'e']);
+        assert_eq!(suppressed(text, "GM1041"), [] as [char; 0]);
+
+        // `ignore` and `restore` are the same as `disable` and `enable`
+        let text = "a\n// Feather ignore GM2017\nb\n// Feather restore GM2017\nc\n";
+        assert_eq!(suppressed(text, "GM2017"), ['b']);
+    }
+
+    #[test]
+    fn ignore_once() {
+        let text = "\
+a
+// Feather ignore once GM2017
+b
+c
+// Feather disable once GM2017
+
+d
+";
+        assert_eq!(suppressed(text, "GM2017"), ['b']);
+    }
+
+    #[test]
+    fn enable_all() {
+        let text = "\
+// Feather disable GM2017
+// Feather disable GM1041
+a
+// Feather enable all
+b
+// Feather disable all
+c
+// Feather enable all
+d
+";
+        assert_eq!(suppressed(text, "GM2017"), ['a', 'c']);
+        assert_eq!(suppressed(text, "GM1041"), ['a', 'c']);
+        assert_eq!(suppressed(text, "GM1100"), ['c']);
+    }
+
+    #[test]
+    fn comma_separated_rules() {
+        let text = "\
+// Feather disable GM2017,GM1041, GM1100
+a
+// Feather enable GM1041
+b
+";
+        assert_eq!(suppressed(text, "GM2017"), ['a', 'b']);
+        assert_eq!(suppressed(text, "GM1041"), ['a']);
+        assert_eq!(suppressed(text, "GM1100"), ['a', 'b']);
+        assert_eq!(suppressed(text, "GM2016"), [] as [char; 0]);
+    }
+
+    #[test]
+    fn trailing_ignore_once() {
+        // a directive after code covers the line below it, not its own line
+        let text = "a // Feather ignore once GM2017\nb\nc\n";
+        assert_eq!(suppressed(text, "GM2017"), ['b']);
+    }
+
+    #[test]
+    fn other_directives_are_left_alone() {
+        let text = "// Feather use syntax-errors\n// Feather\n// feather disable GM2017\na\n";
+        assert_eq!(suppressed(text, "GM2017"), [] as [char; 0]);
+    }
+
+    #[test]
+    fn bad_rule_ids() {
+        let text = "\
+// Feather disable GM3000, GM2017 foo
+a
+// Feather ignore once
+b
+// Feather enable
+";
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        let unknown = text.find("GM3000").unwrap();
+        let foo = text.find("foo").unwrap();
+        let once = text.find("once").unwrap() + "once".len();
+        let enable = text.find("enable").unwrap() + "enable".len();
+        assert_eq!(
+            messages(&tokens),
+            [
+                (UNKNOWN_RULE, range(unknown, unknown + 6)),
+                (UNKNOWN_RULE, range(foo, foo + 3)),
+                (MISSING_RULE, range(once, once)),
+                (MISSING_RULE, range(enable, enable)),
+            ]
+        );
+
+        // the known id in the same directive still applies
+        let rule = RuleId::parse(b"GM2017").unwrap();
+        assert_eq!(
+            tokens.suppressions(),
+            [Suppression {
+                rule: Some(rule),
+                start_line: LineIndex::from(0usize),
+                end_line: LineIndex::from(6usize),
+            }]
+        );
+    }
+}
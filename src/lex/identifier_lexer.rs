@@ -1,18 +1,71 @@
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+use crate::simd::neon;
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+use std::arch::aarch64::*;
+#[cfg(all(target_arch = "x86", not(feature = "no-simd")))]
+use std::arch::x86::*;
+#[cfg(all(target_arch = "x86_64", not(feature = "no-simd")))]
 use std::arch::x86_64::*;
 
 // https://arxiv.org/pdf/1902.08318.pdf
 // idk man it works
 
+#[cfg(all(
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little")
+    ),
+    not(feature = "no-simd")
+))]
 #[repr(align(16))]
 struct NibbleLUT([u8; 16]);
 
+#[cfg(all(
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little")
+    ),
+    not(feature = "no-simd")
+))]
 impl NibbleLUT {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(feature = "no-simd")
+    ))]
     #[inline(always)]
     fn load(&self) -> __m128i {
         unsafe { _mm_load_si128(self.0.as_ptr() as *const __m128i) }
     }
+
+    #[cfg(all(
+        target_arch = "aarch64",
+        target_endian = "little",
+        not(feature = "no-simd")
+    ))]
+    #[inline(always)]
+    fn load_neon(&self) -> uint8x16_t {
+        unsafe { vld1q_u8(self.0.as_ptr()) }
+    }
 }
 
+#[cfg(all(
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little")
+    ),
+    not(feature = "no-simd")
+))]
 static HIGH_LUT: NibbleLUT = NibbleLUT([
     0b0000_0000,
     0b0000_0000,
@@ -32,6 +85,14 @@ static HIGH_LUT: NibbleLUT = NibbleLUT([
     0b1000_0000,
 ]);
 
+#[cfg(all(
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little")
+    ),
+    not(feature = "no-simd")
+))]
 static LOW_LUT: NibbleLUT = NibbleLUT([
     0b1000_1010,
     0b1000_1110,
@@ -52,18 +113,33 @@ static LOW_LUT: NibbleLUT = NibbleLUT([
 ]);
 
 pub fn scan_identifier(text: &[u8]) -> usize {
-    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-        if is_x86_feature_detected!("sse2") {
-            return unsafe { scan_identifier_x86(text) };
-        }
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(feature = "no-simd")
+    ))]
+    if is_x86_feature_detected!("ssse3") {
+        return unsafe { scan_identifier_ssse3(text) };
+    }
+
+    #[cfg(all(
+        target_arch = "aarch64",
+        target_endian = "little",
+        not(feature = "no-simd")
+    ))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        return unsafe { scan_identifier_neon(text) };
     }
 
     scan_identifier_scalar(text, 0)
 }
 
-#[target_feature(enable = "sse2")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn scan_identifier_x86(text: &[u8]) -> usize {
+// the nibble lookups need SSSE3 for `_mm_shuffle_epi8`
+#[target_feature(enable = "ssse3")]
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "no-simd")
+))]
+fn scan_identifier_ssse3(text: &[u8]) -> usize {
     let mut i: usize = 0;
     let size = text.len();
 
@@ -72,18 +148,12 @@ fn scan_identifier_x86(text: &[u8]) -> usize {
 
     unsafe {
         while (i + 16) <= size {
-            let input_ptr = text.as_ptr().offset(i as isize) as *const __m128i;
+            let input_ptr = text.as_ptr().add(i) as *const __m128i;
             let input = _mm_loadu_si128(input_ptr);
 
             // check for non-ASCII characters
-            if cfg!(target_feature = "sse4.1") {
-                if _mm_test_all_zeros(_mm_set1_epi8(0x80u8 as i8), input) == 0 {
-                    break;
-                }
-            } else {
-                if _mm_movemask_epi8(input) != 0 {
-                    break;
-                }
+            if _mm_movemask_epi8(input) != 0 {
+                break;
             }
 
             // low nibble lookup
@@ -110,6 +180,45 @@ fn scan_identifier_x86(text: &[u8]) -> usize {
     scan_identifier_scalar(text, i)
 }
 
+#[target_feature(enable = "neon")]
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+fn scan_identifier_neon(text: &[u8]) -> usize {
+    let mut i: usize = 0;
+    let size = text.len();
+
+    let high_lut = HIGH_LUT.load_neon();
+    let low_lut = LOW_LUT.load_neon();
+    let low_nibble = vdupq_n_u8(0x0f);
+
+    while (i + 16) <= size {
+        let input = unsafe { vld1q_u8(text.as_ptr().add(i)) };
+
+        // check for non-ASCII characters
+        if vmaxvq_u8(input) >= 0x80 {
+            break;
+        }
+
+        // unlike `pshufb`, `tbl` uses the whole byte as the index
+        let low_mask = vqtbl1q_u8(low_lut, vandq_u8(input, low_nibble));
+        let high_mask = vqtbl1q_u8(high_lut, vshrq_n_u8::<4>(input));
+
+        let mask = vandq_u8(low_mask, high_mask);
+        let tail_mask = neon::movemask(vceqzq_u8(mask));
+
+        if tail_mask != 0 {
+            return i + neon::first_match(tail_mask);
+        }
+
+        i += 16;
+    }
+
+    scan_identifier_scalar(text, i)
+}
+
 fn scan_identifier_scalar(text: &[u8], start: usize) -> usize {
     let mut i = start;
 
@@ -123,7 +232,6 @@ fn scan_identifier_scalar(text: &[u8], start: usize) -> usize {
     i
 }
 
-// todo: unicode lexing?
 pub const fn is_identifier_byte(c: u8) -> bool {
     matches!(c, b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'0'..=b'9')
 }
@@ -131,3 +239,65 @@ pub const fn is_identifier_byte(c: u8) -> bool {
 pub const fn is_identifier_start(c: u8) -> bool {
     matches!(c, b'a'..=b'z' | b'A'..=b'Z' | b'_')
 }
+
+pub fn is_unicode_identifier_start(c: char) -> bool {
+    c.is_alphabetic()
+}
+
+pub fn is_unicode_identifier_continue(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Decodes the UTF-8 character at the start of `text`, returning the character and its
+/// length in bytes, or the length of the invalid sequence.
+pub fn decode_utf8_char(text: &[u8]) -> Result<(char, usize), usize> {
+    debug_assert!(!text.is_empty());
+    let prefix = &text[..text.len().min(4)];
+    let valid = match std::str::from_utf8(prefix) {
+        Ok(valid) => valid,
+        Err(error) if error.valid_up_to() > 0 => {
+            std::str::from_utf8(&prefix[..error.valid_up_to()]).unwrap()
+        }
+        Err(error) => return Err(error.error_len().unwrap_or(prefix.len())),
+    };
+
+    let c = valid.chars().next().unwrap();
+    Ok((c, c.len_utf8()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every byte value at every offset of identifiers up to three chunks long, so each
+    // non-ASCII byte is seen at every position of a 16-byte window. With `no-simd`, this
+    // checks the scalar scanner alone.
+    #[test]
+    fn scan_identifier_matches_scalar() {
+        for len in 0..=48 {
+            let mut text = vec![b'a'; len];
+            for offset in 0..len {
+                for byte in 0..=u8::MAX {
+                    text[offset] = byte;
+                    let expected = if is_identifier_byte(byte) {
+                        len
+                    } else {
+                        offset
+                    };
+                    assert_eq!(scan_identifier_scalar(&text, 0), expected, "{text:?}");
+                    assert_eq!(scan_identifier(&text), expected, "{text:?}");
+
+                    #[cfg(all(
+                        any(target_arch = "x86", target_arch = "x86_64"),
+                        not(feature = "no-simd")
+                    ))]
+                    if is_x86_feature_detected!("ssse3") {
+                        let len = unsafe { scan_identifier_ssse3(&text) };
+                        assert_eq!(len, expected, "{text:?}");
+                    }
+                }
+                text[offset] = b'a';
+            }
+        }
+    }
+}
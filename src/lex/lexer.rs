@@ -1,10 +1,17 @@
+use crate::lex::feather::{is_suppression_error, read_suppressions};
 use crate::lex::identifier_lexer::*;
-use crate::lex::number_lexer::scan_number_or_dot;
-use crate::lex::string_lexer::{scan_string_literal, scan_verbatim_string_literal};
-use crate::lex::token::{Token, TokenIndex, TokenKind};
-use crate::lex::{Comment, Line, LineIndex, TokenizedText};
+use crate::lex::number_lexer::{
+    parse_number_literal, scan_binary_literal, scan_hex_literal, scan_number_or_dot,
+};
+use crate::lex::string_lexer::{
+    TemplateStop, scan_string_literal, scan_template_segment, scan_verbatim_string_literal,
+};
+use crate::lex::token::{QuoteStyle, Token, TokenIndex, TokenKind};
+use crate::lex::{Line, LineEnding, LineIndex, TokenizedText, Trivia, TriviaIndex};
 use crate::parse::ParseDiagnostic;
-use crate::source_text::{SourceText, TextSize};
+use crate::simd::{find_comment_end, horizontal_whitespace_len};
+use crate::source_text::{SourceText, TextEdit, TextRange, TextSize};
+use crate::user_symbols::UserSymbols;
 
 #[derive(Copy, Clone)]
 #[repr(u8)]
@@ -44,6 +51,7 @@ pub enum Dispatch {
     Pipe,
     BraceClose,
     Tilde,
+    Backslash,
     Unicode,
 }
 
@@ -87,6 +95,7 @@ static DISPATCH_TABLE: [Dispatch; CHAR_COUNT] = {
             b'|' => Dispatch::Pipe,
             b'}' => Dispatch::BraceClose,
             b'~' => Dispatch::Tilde,
+            b'\\' => Dispatch::Backslash,
             c if is_identifier_start(c) => Dispatch::IdentifierStart,
             c if is_horizontal_whitespace(c) => Dispatch::HorizontalSpace,
             0x00..=0x7F => Dispatch::Error,
@@ -101,7 +110,7 @@ const fn is_horizontal_whitespace(c: u8) -> bool {
     matches!(c, b' ' | b'\t')
 }
 
-const fn is_open_delimiter(kind: TokenKind) -> bool {
+pub(crate) const fn is_open_delimiter(kind: TokenKind) -> bool {
     matches!(
         kind,
         TokenKind::LeftParen
@@ -112,16 +121,92 @@ const fn is_open_delimiter(kind: TokenKind) -> bool {
             | TokenKind::GridAccessor
             | TokenKind::MapAccessor
             | TokenKind::StructAccessor
+            | TokenKind::TemplateStart
+            | TokenKind::TemplateMiddle
+    )
+}
+
+pub(crate) const fn is_close_delimiter(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::RightParen
+            | TokenKind::RightBrace
+            | TokenKind::RightSquare
+            | TokenKind::TemplateMiddle
+            | TokenKind::TemplateEnd
+    )
+}
+
+// An open template segment on the delimiter stack means that the next `}` resumes the
+// template text instead of closing a brace.
+const fn is_template_open(kind: TokenKind) -> bool {
+    matches!(kind, TokenKind::TemplateStart | TokenKind::TemplateMiddle)
+}
+
+const UNMATCHED_CLOSE_DELIMITER: &str = "closing delimiter has no opening delimiter";
+const MISMATCHED_OPEN_DELIMITER: &str = "opening delimiter is closed by a mismatched delimiter";
+const MISMATCHED_CLOSE_DELIMITER: &str = "mismatched closing delimiter";
+const UNCLOSED_DELIMITER: &str = "unclosed delimiter";
+const UNCLOSED_INTERPOLATION: &str = "unclosed template string interpolation";
+
+// delimiter errors are reported again whenever the text is edited
+fn is_delimiter_error(message: &str) -> bool {
+    matches!(
+        message,
+        UNMATCHED_CLOSE_DELIMITER
+            | MISMATCHED_OPEN_DELIMITER
+            | MISMATCHED_CLOSE_DELIMITER
+            | UNCLOSED_DELIMITER
+            | UNCLOSED_INTERPOLATION
     )
 }
 
-const fn is_close_delimiter(kind: TokenKind) -> bool {
+fn unclosed_delimiter_message(kind: TokenKind) -> &'static str {
+    if is_template_open(kind) {
+        UNCLOSED_INTERPOLATION
+    } else {
+        UNCLOSED_DELIMITER
+    }
+}
+
+const fn is_gms23_keyword(kind: TokenKind) -> bool {
     matches!(
         kind,
-        TokenKind::RightParen | TokenKind::RightBrace | TokenKind::RightSquare
+        TokenKind::Static
+            | TokenKind::Function
+            | TokenKind::Constructor
+            | TokenKind::New
+            | TokenKind::Delete
+            | TokenKind::Try
+            | TokenKind::Catch
+            | TokenKind::Finally
+            | TokenKind::Throw
     )
 }
 
+// Leaves room for the tokens that a single dispatch can add, and for the final FileEnd.
+const TOKEN_LIMIT: usize = Token::MAX_INDEX - 16;
+
+// How many tokens an edit may have to follow from the last delimiter snapshot before it.
+const SNAPSHOT_INTERVAL: usize = 1024;
+
+// Delimiter errors are kept with the tokens, so that an edit only has to find the ones
+// around it again.
+pub(crate) struct DelimiterError {
+    message: &'static str,
+    token: TokenIndex,
+    // the number of tokens that were lexed when the error was found
+    found_at: usize,
+}
+
+// The delimiters that are open when a line is entered, saved every so often so that an
+// edit doesn't have to follow every delimiter before it.
+pub(crate) struct DelimiterSnapshot {
+    // the first token after the line break
+    token: TokenIndex,
+    open_delimiters: Vec<TokenIndex>,
+}
+
 fn is_matching_delimiter(open_kind: TokenKind, close_kind: TokenKind) -> bool {
     debug_assert!(is_open_delimiter(open_kind));
     debug_assert!(is_close_delimiter(close_kind));
@@ -134,36 +219,228 @@ fn is_matching_delimiter(open_kind: TokenKind, close_kind: TokenKind) -> bool {
         | TokenKind::GridAccessor
         | TokenKind::MapAccessor
         | TokenKind::StructAccessor => close_kind == TokenKind::RightSquare,
+        TokenKind::TemplateStart | TokenKind::TemplateMiddle => {
+            matches!(
+                close_kind,
+                TokenKind::TemplateMiddle | TokenKind::TemplateEnd
+            )
+        }
         _ => panic!("expected an open delimiter"),
     }
 }
 
+/// How the lexer treats line endings other than LF and CR+LF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEndingPolicy {
+    /// Every line ending starts a new line.
+    Accept,
+    /// Every line ending starts a new line, and the file's line ending is recorded in its
+    /// normalized form, so that a formatter writes back LF or CR+LF.
+    Normalize,
+    /// Only LF and CR+LF start a new line. Any other CR is reported and treated as
+    /// whitespace.
+    #[default]
+    Reject,
+}
+
+/// The GameMaker version that a file is written for, which decides the words that are
+/// keywords rather than identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LanguageVersion {
+    /// GameMaker Studio 2.2 and earlier, where `static`, `function`, `constructor`, `new`,
+    /// `delete`, `try`, `catch`, `finally` and `throw` are identifiers.
+    Gms22,
+    /// GameMaker Studio 2.3 and later, which added structs, methods and exceptions. `begin`
+    /// and `end` are reported as deprecated.
+    #[default]
+    Gms23,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexOptions {
+    pub line_endings: LineEndingPolicy,
+    pub language_version: LanguageVersion,
+}
+
 pub fn lex(text: &SourceText) -> TokenizedText {
-    let mut lexer = Lexer::new(text);
+    lex_with_options(text, &LexOptions::default(), None)
+}
+
+/// Lexes `text` and interns identifiers and number literal values into `symbols`, storing
+/// their ids in the token payloads. Sharing `symbols` between files gives identifiers the
+/// same id across a whole project. String literals are only decoded when they're needed,
+/// so their values are interned with `TokenizedText::intern_string_literal`.
+pub fn lex_with_symbols<'src>(
+    text: &'src SourceText,
+    symbols: &mut UserSymbols<'src>,
+) -> TokenizedText {
+    lex_with_options(text, &LexOptions::default(), Some(symbols))
+}
+
+pub fn lex_with_options<'src>(
+    text: &'src SourceText,
+    options: &LexOptions,
+    symbols: Option<&mut UserSymbols<'src>>,
+) -> TokenizedText {
+    let mut lexer = Lexer::new(text, *options, symbols);
     lexer.lex();
     lexer.output
 }
 
-struct Lexer<'a> {
+/// Lexes `text` after `edit`, updating `tokens`, which were lexed from the text before the
+/// edit.
+pub(crate) fn relex<'src>(
+    tokens: TokenizedText,
+    text: &'src SourceText,
+    edit: TextEdit,
+    symbols: Option<&mut UserSymbols<'src>>,
+) -> TokenizedText {
+    let old_len = tokens.get_start(TokenIndex::from(tokens.token_count() - 1));
+    debug_assert!(edit.range.end() <= old_len);
+    debug_assert!(old_len + edit.new_len == text.len() + edit.range.len());
+
+    let mut lexer = Lexer::new(text, tokens.options, symbols);
+    lexer.output = tokens;
+    lexer.relex(edit);
+    lexer.output
+}
+
+// `begin` and `end` are lexed as braces, but don't open or close anything
+fn is_delimiter_token(tokens: &TokenizedText, token: TokenIndex) -> bool {
+    let kind = tokens.get_kind(token);
+    match kind {
+        TokenKind::LeftBrace | TokenKind::RightBrace => {
+            tokens.get_end(token) - tokens.get_start(token) == 1
+        }
+        _ => is_open_delimiter(kind) || is_close_delimiter(kind),
+    }
+}
+
+// whether the line ended between `token` and the one before it
+fn follows_line_break(tokens: &TokenizedText, token: TokenIndex) -> bool {
+    let (first, last) = tokens.trivia_before(token);
+    (usize::from(first)..usize::from(last))
+        .any(|index| tokens.get_trivia(index.into()).kind() == TokenKind::LineBreak)
+}
+
+// the open template segment that is lowest on the delimiter stack
+fn find_open_template(tokens: &TokenizedText, open_delimiters: &[TokenIndex]) -> Option<usize> {
+    open_delimiters
+        .iter()
+        .position(|&token| is_template_open(tokens.get_kind(token)))
+}
+
+// Updates `open_delimiters` for a token that was already lexed, the same way as when it
+// was lexed, without finding its errors again.
+fn follow_delimiters(
+    tokens: &TokenizedText,
+    open_delimiters: &mut Vec<TokenIndex>,
+    token: TokenIndex,
+) {
+    if follows_line_break(tokens, token) {
+        end_interpolations(tokens, open_delimiters);
+    }
+    if is_delimiter_token(tokens, token) {
+        let kind = tokens.get_kind(token);
+        if is_close_delimiter(kind) {
+            open_delimiters.pop();
+        }
+        if is_open_delimiter(kind) {
+            open_delimiters.push(token);
+        }
+    }
+}
+
+// mirrors `Lexer::end_interpolations_at_line_break`
+fn end_interpolations(tokens: &TokenizedText, open_delimiters: &mut Vec<TokenIndex>) {
+    if let Some(position) = find_open_template(tokens, open_delimiters) {
+        open_delimiters.truncate(position);
+    }
+}
+
+// Follows the old tokens while the edited lines are lexed again, to find a line where
+// the lexer is in the same state as it was before the edit.
+struct Resync {
+    edit: TextEdit,
+    // the next old token to follow, and the end of the old tokens
+    token: TokenIndex,
+    end: TokenIndex,
+    open_delimiters: Vec<TokenIndex>,
+}
+
+impl Resync {
+    fn advance(&mut self, tokens: &TokenizedText) {
+        follow_delimiters(tokens, &mut self.open_delimiters, self.token);
+        self.token += 1;
+    }
+}
+
+// where `Lexer::scan_lines` stopped
+enum LinesEnd {
+    // the old lines can be reused from the line that starts here
+    Resync(TextSize),
+    FileEnd { last_line_is_inserted: bool },
+}
+
+// What is kept from the old tokens when an edit is lexed again
+struct Restart {
+    token: TokenIndex,
+    trivia: TriviaIndex,
+    // the new tokens and trivia are added after the old ones, from here on
+    new_tokens: TokenIndex,
+    new_trivia: TriviaIndex,
+    // the old lines that were found again, and the end of the new ones
+    old_lines: Vec<Line>,
+    lines_end: LineIndex,
+    // the old payloads of the delimiters that are open at `token`
+    open_payloads: Vec<(TokenIndex, u32)>,
+    // everything that was found from `token` on, for the tokens that are reused
+    diagnostics: Vec<ParseDiagnostic>,
+    delimiter_errors: Vec<DelimiterError>,
+    delimiter_snapshots: Vec<DelimiterSnapshot>,
+}
+
+struct Lexer<'a, 'src> {
     output: TokenizedText,
-    text: &'a SourceText,
+    text: &'src SourceText<'src>,
+    options: LexOptions,
+    symbols: Option<&'a mut UserSymbols<'src>>,
     cursor: TextSize,
     line_index: LineIndex,
     open_delimiters: Vec<TokenIndex>,
     has_leading_space: bool,
-    has_mismatched_brackets: bool,
+    macro_body: Option<TokenIndex>,
+    // set when the lexer moves to the start of a line after a line break
+    entered_line: bool,
+    // While an edit is lexed again, the new tokens are added after the old ones, from
+    // `window_start` on, and end up `window_shift` lower. Payloads, errors and snapshots
+    // store the final indices.
+    window_start: TokenIndex,
+    window_shift: usize,
 }
 
-impl<'a> Lexer<'a> {
-    fn new(text: &'a SourceText) -> Self {
+impl<'a, 'src> Lexer<'a, 'src> {
+    fn new(
+        text: &'src SourceText<'src>,
+        options: LexOptions,
+        symbols: Option<&'a mut UserSymbols<'src>>,
+    ) -> Self {
+        let mut output = TokenizedText::new();
+        output.options = options;
+
         Self {
-            output: TokenizedText::new(),
+            output,
             text,
+            options,
+            symbols,
             cursor: TextSize::from(0),
             line_index: LineIndex::from(0),
             open_delimiters: Vec::new(),
             has_leading_space: false,
-            has_mismatched_brackets: false,
+            macro_body: None,
+            entered_line: false,
+            window_start: TokenIndex::from(0),
+            window_shift: 0,
         }
     }
 
@@ -172,98 +449,133 @@ impl<'a> Lexer<'a> {
         self.lex_file_start();
 
         while self.cursor < self.text.len() {
-            // dispatch table covers all possible u8 values
-            let dispatch_kind = unsafe {
-                *DISPATCH_TABLE.get_unchecked(self.text.get_byte_unchecked(self.cursor) as usize)
-            };
-            match dispatch_kind {
-                Dispatch::IdentifierStart => self.lex_keyword_or_identifier(),
-                Dispatch::Dot => self.lex_number_literal_or_dot(),
-                Dispatch::DigitZero => self.lex_number_literal_or_dot(),
-                Dispatch::DigitNonZero => self.lex_number_literal_or_dot(),
-                Dispatch::Quote => self.lex_string_literal(),
-                Dispatch::At => self.lex_verbatim_string_literal(),
-                Dispatch::Dollar => self.lex_template_string_or_hex_literal(),
-                Dispatch::HorizontalSpace => self.lex_horizontal_whitespace(),
-                Dispatch::NewLine => self.lex_vertical_whitespace(),
-                Dispatch::Cr => self.lex_cr(),
-                Dispatch::Slash => self.lex_comment_or_divide(),
-                Dispatch::Hash => todo!("hex literal + directives"),
-
-                Dispatch::BracketOpen => self.lex_accessor(),
-                Dispatch::BracketClose => self.lex_close_delimiter(TokenKind::RightSquare),
-                Dispatch::ParenOpen => self.lex_open_delimiter(TokenKind::LeftParen),
-                Dispatch::ParenClose => self.lex_close_delimiter(TokenKind::RightParen),
-                Dispatch::BraceOpen => self.lex_open_delimiter(TokenKind::LeftBrace),
-                Dispatch::BraceClose => self.lex_close_delimiter(TokenKind::RightBrace),
-
-                Dispatch::Comma => self.lex_byte(TokenKind::Comma),
-                Dispatch::Colon => self.lex_byte(TokenKind::Colon),
-                Dispatch::Semicolon => self.lex_byte(TokenKind::Semicolon),
-
-                Dispatch::Exclamation => {
-                    self.lex_byte_and_equals(self.cursor, TokenKind::Not, TokenKind::NotEquals)
-                }
-                Dispatch::Percent => self.lex_byte_and_equals(
-                    self.cursor,
-                    TokenKind::Modulo,
-                    TokenKind::ModuloAssign,
-                ),
-                Dispatch::Caret => self.lex_byte_and_equals(
-                    self.cursor,
-                    TokenKind::BitXor,
-                    TokenKind::BitXorAssign,
-                ),
-                Dispatch::Tilde => self.lex_byte_and_equals(
-                    self.cursor,
-                    TokenKind::BitNot,
-                    TokenKind::BitNotAssign,
-                ),
-                Dispatch::Equal => {
-                    self.lex_byte_and_equals(self.cursor, TokenKind::Equals, TokenKind::Equals)
-                }
-
-                Dispatch::Ampersand => self.lex_byte_twice_or_equals(
-                    TokenKind::BitAnd,
-                    TokenKind::And,
-                    TokenKind::BitAndAssign,
-                ),
-                Dispatch::Asterisk => self.lex_byte_twice_or_equals(
-                    TokenKind::Multiply,
-                    TokenKind::Power,
-                    TokenKind::MultiplyAssign,
-                ),
-                Dispatch::Plus => self.lex_byte_twice_or_equals(
-                    TokenKind::Plus,
-                    TokenKind::PlusPlus,
-                    TokenKind::PlusAssign,
-                ),
-                Dispatch::Minus => self.lex_byte_twice_or_equals(
-                    TokenKind::Minus,
-                    TokenKind::MinusMinus,
-                    TokenKind::MinusAssign,
-                ),
-                Dispatch::Pipe => self.lex_byte_twice_or_equals(
-                    TokenKind::BitOr,
-                    TokenKind::Or,
-                    TokenKind::BitOrAssign,
-                ),
-                Dispatch::LessThan => self.lex_less_than(),
-                Dispatch::GreaterThan => self.lex_greater_than(),
-                Dispatch::Question => self.lex_question(),
-
-                Dispatch::Unicode => todo!("unicode"),
-                Dispatch::Error => self.lex_error(),
-            };
+            if self.token_count() >= TOKEN_LIMIT {
+                self.lex_token_limit_exceeded();
+                break;
+            }
+            self.lex_next();
         }
 
         self.lex_file_end();
+        self.close_unclosed_delimiters();
+        self.report_delimiter_errors();
+        let trivia_count = TriviaIndex::from(self.output.trivia_count());
+        self.output
+            .attach_comments(TokenIndex::from(0), TriviaIndex::from(0), trivia_count);
+        self.read_suppressions();
+    }
+
+    fn lex_next(&mut self) {
+        // dispatch table covers all possible u8 values
+        let dispatch_kind = unsafe {
+            *DISPATCH_TABLE.get_unchecked(self.text.get_byte_unchecked(self.cursor) as usize)
+        };
+        match dispatch_kind {
+            Dispatch::IdentifierStart => self.lex_keyword_or_identifier(),
+            Dispatch::Dot => self.lex_number_literal_or_dot(),
+            Dispatch::DigitZero => self.lex_number_literal_or_dot(),
+            Dispatch::DigitNonZero => self.lex_number_literal_or_dot(),
+            Dispatch::Quote => self.lex_string_literal(),
+            Dispatch::At => self.lex_verbatim_string_literal(),
+            Dispatch::Dollar => self.lex_template_string_or_hex_literal(),
+            Dispatch::HorizontalSpace => self.lex_horizontal_whitespace(),
+            Dispatch::NewLine => self.lex_vertical_whitespace(),
+            Dispatch::Cr => self.lex_cr(),
+            Dispatch::Slash => self.lex_comment_or_divide(),
+            Dispatch::Hash => self.lex_hash(),
+            Dispatch::Backslash => self.lex_backslash(),
+
+            Dispatch::BracketOpen => self.lex_accessor(),
+            Dispatch::BracketClose => self.lex_close_delimiter(TokenKind::RightSquare),
+            Dispatch::ParenOpen => self.lex_open_delimiter(TokenKind::LeftParen),
+            Dispatch::ParenClose => self.lex_close_delimiter(TokenKind::RightParen),
+            Dispatch::BraceOpen => self.lex_open_delimiter(TokenKind::LeftBrace),
+            Dispatch::BraceClose => self.lex_brace_close(),
+
+            Dispatch::Comma => self.lex_byte(TokenKind::Comma),
+            Dispatch::Colon => self.lex_byte(TokenKind::Colon),
+            Dispatch::Semicolon => self.lex_byte(TokenKind::Semicolon),
+
+            Dispatch::Exclamation => {
+                self.lex_byte_and_equals(self.cursor, TokenKind::Not, TokenKind::NotEquals)
+            }
+            Dispatch::Percent => {
+                self.lex_byte_and_equals(self.cursor, TokenKind::Modulo, TokenKind::ModuloAssign)
+            }
+            Dispatch::Caret => {
+                self.lex_byte_and_equals(self.cursor, TokenKind::BitXor, TokenKind::BitXorAssign)
+            }
+            Dispatch::Tilde => {
+                self.lex_byte_and_equals(self.cursor, TokenKind::BitNot, TokenKind::BitNotAssign)
+            }
+            Dispatch::Equal => {
+                self.lex_byte_and_equals(self.cursor, TokenKind::Equals, TokenKind::Equals)
+            }
+
+            Dispatch::Ampersand => self.lex_byte_twice_or_equals(
+                TokenKind::BitAnd,
+                TokenKind::And,
+                TokenKind::BitAndAssign,
+            ),
+            Dispatch::Asterisk => self.lex_byte_twice_or_equals(
+                TokenKind::Multiply,
+                TokenKind::Power,
+                TokenKind::MultiplyAssign,
+            ),
+            Dispatch::Plus => self.lex_byte_twice_or_equals(
+                TokenKind::Plus,
+                TokenKind::PlusPlus,
+                TokenKind::PlusAssign,
+            ),
+            Dispatch::Minus => self.lex_byte_twice_or_equals(
+                TokenKind::Minus,
+                TokenKind::MinusMinus,
+                TokenKind::MinusAssign,
+            ),
+            Dispatch::Pipe => self.lex_byte_twice_or_equals(
+                TokenKind::BitOr,
+                TokenKind::Or,
+                TokenKind::BitOrAssign,
+            ),
+            Dispatch::LessThan => self.lex_less_than(),
+            Dispatch::GreaterThan => self.lex_greater_than(),
+            Dispatch::Question => self.lex_question(),
+
+            Dispatch::Unicode => self.lex_unicode(),
+            Dispatch::Error => self.lex_error(),
+        };
+    }
 
-        if self.output.token_count() >= Token::MAX_INDEX {
-            todo!("report too many tokens");
+    fn lex_token_limit_exceeded(&mut self) {
+        let start = self.cursor;
+        self.cursor = self.text.len();
+        self.report(
+            "too many tokens, the rest of the file was not lexed",
+            start,
+            self.cursor,
+        );
+        self.add_error_token(start, self.cursor - start);
+    }
+
+    fn token_count(&self) -> usize {
+        self.output.token_count() - self.window_shift
+    }
+
+    // the index that a token will have once an edit is lexed again
+    fn final_index(&self, token: TokenIndex) -> TokenIndex {
+        if token >= self.window_start {
+            token - self.window_shift
+        } else {
+            token
         }
     }
 
+    fn report(&mut self, message: &'static str, start: TextSize, end: TextSize) {
+        self.output
+            .diagnostics
+            .push(ParseDiagnostic::new(message, start, end));
+    }
+
     fn add_token(&mut self, kind: TokenKind, start: TextSize) -> TokenIndex {
         Self::add_token_with_payload(self, kind, 0, start)
     }
@@ -279,8 +591,25 @@ impl<'a> Lexer<'a> {
         self.output.add_token(token)
     }
 
+    // error tokens store their length in the payload
+    fn add_error_token(&mut self, start: TextSize, len: TextSize) {
+        let payload = usize::from(len).min(Token::MAX_INDEX - 1);
+        self.add_token_with_payload(TokenKind::Error, payload as u32, start);
+    }
+
+    // whitespace, line breaks and comments are kept as trivia
+    fn add_trivia(&mut self, kind: TokenKind, start: TextSize, end: TextSize) {
+        if start < end {
+            self.output.add_trivia(Trivia::new(kind, start, end));
+        }
+    }
+
     fn current(&self) -> u8 {
-        self.text.get_byte(self.cursor)
+        if self.cursor < self.text.len() {
+            self.text.get_byte(self.cursor)
+        } else {
+            0
+        }
     }
 
     fn peek(&self) -> u8 {
@@ -310,6 +639,19 @@ impl<'a> Lexer<'a> {
         self.handle_close_delimiter(token_index);
     }
 
+    fn lex_brace_close(&mut self) {
+        let in_template = match self.open_delimiters.last() {
+            Some(&open_token_index) => is_template_open(self.output.get_kind(open_token_index)),
+            None => false,
+        };
+
+        if in_template {
+            self.lex_template_continuation();
+        } else {
+            self.lex_close_delimiter(TokenKind::RightBrace);
+        }
+    }
+
     fn lex_byte_and_equals(&mut self, start: TextSize, kind: TokenKind, equals_kind: TokenKind) {
         if self.peek() == b'=' {
             self.cursor += 2;
@@ -348,24 +690,82 @@ impl<'a> Lexer<'a> {
     fn handle_close_delimiter(&mut self, close_token_index: TokenIndex) {
         if let Some(open_token_index) = self.open_delimiters.pop() {
             // store the matching delimiter in the payload
+            let open_payload = self.final_index(close_token_index).value();
+            let close_payload = self.final_index(open_token_index).value();
             let close_kind: TokenKind;
             {
                 let close_token = self.output.tokens.get_mut(close_token_index);
-                close_token.set_payload(open_token_index.value());
+                close_token.set_payload(close_payload);
                 close_kind = close_token.kind();
             }
 
             let open_token = self.output.tokens.get_mut(open_token_index);
             if is_matching_delimiter(open_token.kind(), close_kind) {
-                open_token.set_payload(close_token_index.value());
+                open_token.set_payload(open_payload);
             } else {
-                self.has_mismatched_brackets = true;
+                self.add_delimiter_error(MISMATCHED_OPEN_DELIMITER, open_token_index);
+                self.add_delimiter_error(MISMATCHED_CLOSE_DELIMITER, close_token_index);
             }
         } else {
-            self.has_mismatched_brackets = true;
+            self.add_delimiter_error(UNMATCHED_CLOSE_DELIMITER, close_token_index);
+        }
+    }
+
+    fn add_delimiter_error(&mut self, message: &'static str, token: TokenIndex) {
+        let error = DelimiterError {
+            message,
+            token: self.final_index(token),
+            found_at: self.token_count(),
+        };
+        self.output.delimiter_errors.push(error);
+    }
+
+    // Interpolations can't span lines, so a template that is still open at a line break
+    // is unclosed, along with any delimiters opened inside it. A `}` on a later line then
+    // closes a brace instead of resuming the template.
+    fn end_interpolations_at_line_break(&mut self) {
+        let Some(position) = find_open_template(&self.output, &self.open_delimiters) else {
+            return;
+        };
+
+        for token in self.open_delimiters.split_off(position) {
+            let message = unclosed_delimiter_message(self.output.get_kind(token));
+            self.add_delimiter_error(message, token);
         }
     }
 
+    fn close_unclosed_delimiters(&mut self) {
+        for token in std::mem::take(&mut self.open_delimiters) {
+            let message = unclosed_delimiter_message(self.output.get_kind(token));
+            self.add_delimiter_error(message, token);
+        }
+    }
+
+    // Delimiter errors point at whole tokens, so they're reported once every token is in
+    // place, in the order they were found.
+    fn report_delimiter_errors(&mut self) {
+        self.output.has_mismatched_brackets = !self.output.delimiter_errors.is_empty();
+        for index in 0..self.output.delimiter_errors.len() {
+            let DelimiterError { message, token, .. } = self.output.delimiter_errors[index];
+            let end = self.output.get_end(token);
+            // an unclosed interpolation is reported at the `{` that ends its segment
+            let start = if message == UNCLOSED_INTERPOLATION {
+                end - 1
+            } else {
+                self.output.get_start(token)
+            };
+            self.report(message, start, end);
+        }
+    }
+
+    // Suppressions are read from every comment once they're attached, since a directive
+    // can affect the rest of the file.
+    fn read_suppressions(&mut self) {
+        let (suppressions, diagnostics) = read_suppressions(&self.output, self.text);
+        self.output.suppressions = suppressions;
+        self.output.diagnostics.extend(diagnostics);
+    }
+
     fn lex_file_start(&mut self) {
         debug_assert!(self.cursor == 0);
         self.add_token(TokenKind::FileStart, TextSize::from(0));
@@ -379,73 +779,245 @@ impl<'a> Lexer<'a> {
 
     fn lex_file_end(&mut self) {
         debug_assert!(self.cursor == self.text.len());
+        self.finish_macro_body();
         self.has_leading_space = true;
         self.add_token(TokenKind::FileEnd, self.cursor);
     }
 
     fn make_lines(&mut self) {
-        let Lexer {
-            text,
-            output: tokens,
-            ..
-        } = self;
-
-        if text.len() == 0 {
-            tokens.lines.push(Line::new(TextSize::from(0)));
-            return;
+        let accepts_cr = self.options.line_endings != LineEndingPolicy::Reject;
+        let output = &mut self.output;
+        let start = TextSize::from(0);
+        let end = Self::scan_lines(self.text, accepts_cr, start, start, None, |line| {
+            output.add_line(line)
+        });
+        if let LinesEnd::FileEnd {
+            last_line_is_inserted,
+        } = end
+        {
+            output.last_line_is_inserted = last_line_is_inserted;
         }
+        Self::record_line_ending(output, self.options.line_endings);
+    }
 
-        let mut start: TextSize = 0.into();
+    // Finds the lines of `text` from `start`, which is the start of a line, and passes
+    // each one to `add_line`. Line breaks are looked for from `scan_start`, which is after
+    // `start` when the CR of an LF+CR line ending is whitespace. With `resync_after`,
+    // stops after the first line that ends with a line break past that offset and right
+    // after something other than a line break, since the line breaks from there on are
+    // found the same way as in the text before an edit.
+    fn scan_lines(
+        text: &SourceText,
+        accepts_cr: bool,
+        start: TextSize,
+        scan_start: TextSize,
+        resync_after: Option<TextSize>,
+        mut add_line: impl FnMut(Line),
+    ) -> LinesEnd {
+        let mut start = start;
+        let mut ignored_crs = 0;
+        let mut next_lf = text.find_next(b'\n', scan_start);
+        let mut next_cr = text.find_next(b'\r', scan_start);
 
         loop {
-            match text.find_next(b'\n', start) {
-                Some(new_line_start) => {
-                    tokens.lines.push(Line::new(start));
-                    start = new_line_start + 1;
-                }
-                None => {
-                    break;
+            let position = match (next_lf, next_cr) {
+                (Some(lf), Some(cr)) => lf.min(cr),
+                (Some(lf), None) => lf,
+                (None, Some(cr)) => cr,
+                (None, None) => break,
+            };
+
+            let byte_at = |offset: usize| {
+                let index = position + offset;
+                if index < text.len() {
+                    text.get_byte(index)
+                } else {
+                    0
                 }
             };
+            let (ending, len) = match (byte_at(0), byte_at(1), byte_at(2)) {
+                (b'\n', b'\r', next) if next != b'\n' => (LineEnding::LfCr, 2),
+                (b'\n', _, _) => (LineEnding::Lf, 1),
+                (_, b'\n', _) => (LineEnding::CrLf, 2),
+                _ => (LineEnding::Cr, 1),
+            };
+
+            // without CR line endings, the CR of LF+CR is whitespace on the next line
+            let line_break_len = match ending {
+                LineEnding::Cr if !accepts_cr => 0,
+                LineEnding::LfCr if !accepts_cr => 1,
+                _ => len,
+            };
+            if line_break_len > 0 {
+                add_line(Line::with_ending(start, Some(ending), ignored_crs));
+                ignored_crs = 0;
+                start = position + line_break_len;
+
+                if resync_after.is_some_and(|offset| position > offset)
+                    && !matches!(text.get_byte(position - 1), b'\n' | b'\r')
+                {
+                    return LinesEnd::Resync(start);
+                }
+            } else {
+                ignored_crs += 1;
+            }
+
+            let end = position + len;
+            if next_lf.is_some_and(|lf| lf < end) {
+                next_lf = text.find_next(b'\n', end);
+            }
+            if next_cr.is_some_and(|cr| cr < end) {
+                next_cr = text.find_next(b'\r', end);
+            }
         }
 
         // The last line ends at the end of the file
-        tokens.lines.push(Line::new(start));
+        add_line(Line::with_ending(start, None, ignored_crs));
 
         // If the last line wasn't empty, insert a fake blank line
-        if start != text.len() {
-            tokens.lines.push(Line::new(text.len()));
-            tokens.last_line_is_inserted = true;
+        let last_line_is_inserted = start != text.len();
+        if last_line_is_inserted {
+            add_line(Line::new(text.len()));
+        }
+        LinesEnd::FileEnd {
+            last_line_is_inserted,
+        }
+    }
+
+    fn record_line_ending(tokens: &mut TokenizedText, policy: LineEndingPolicy) {
+        const ENDINGS: [LineEnding; 4] = [
+            LineEnding::Lf,
+            LineEnding::CrLf,
+            LineEnding::Cr,
+            LineEnding::LfCr,
+        ];
+        let counts = tokens.line_ending_counts;
+
+        // ties go to the ending that comes first, LF before CR+LF
+        let mut most_common = 0;
+        for (index, &count) in counts.iter().enumerate() {
+            if count > counts[most_common] {
+                most_common = index;
+            }
         }
+
+        let ending = ENDINGS[most_common];
+        tokens.line_ending = if policy == LineEndingPolicy::Normalize {
+            ending.normalized()
+        } else {
+            ending
+        };
+        tokens.has_mixed_line_endings = counts.iter().filter(|&&count| count > 0).count() > 1;
     }
 
     fn advance_to_line(&mut self, to_line: LineIndex) {
         debug_assert!(to_line > self.line_index || (to_line == 0 && self.line_index == 0));
+        if usize::from(to_line) >= self.output.lines.len() {
+            self.cursor = self.text.len();
+            return;
+        }
+
         self.line_index = to_line;
-        self.cursor = self.output.lines.get(to_line).start();
+        let line_start = self.output.lines.get(to_line).start();
+        self.cursor = line_start;
         self.skip_horizontal_whitespace();
+        self.add_trivia(TokenKind::Whitespace, line_start, self.cursor);
         let line_info = self.output.lines.get_mut(self.line_index);
         line_info.set_indent((self.cursor - line_info.start()).value());
+        self.entered_line = true;
+        self.save_delimiter_snapshot();
+    }
+
+    fn save_delimiter_snapshot(&mut self) {
+        let token = self.token_count();
+        if let Some(last) = self.output.delimiter_snapshots.last()
+            && token < usize::from(last.token) + SNAPSHOT_INTERVAL
+        {
+            return;
+        }
+
+        let snapshot = DelimiterSnapshot {
+            token: TokenIndex::from(token),
+            open_delimiters: self
+                .open_delimiters
+                .iter()
+                .map(|&token| self.final_index(token))
+                .collect(),
+        };
+        self.output.delimiter_snapshots.push(snapshot);
     }
 
     fn advance_to_next_line(&mut self) {
-        self.advance_to_line(self.line_index + 1);
+        self.end_macro_body_at_line_break();
+        self.end_interpolations_at_line_break();
+        self.skip_covered_lines();
+
+        let line_count = self.output.lines.len();
+        let next_line = self.line_index + 1;
+        let next_line_start = if usize::from(next_line) < line_count {
+            self.output.lines.get(next_line).start()
+        } else {
+            self.text.len()
+        };
+        self.add_trivia(TokenKind::LineBreak, self.cursor, next_line_start);
+
+        self.advance_to_line(next_line);
     }
 
-    fn skip_horizontal_whitespace(&mut self) {
-        while self.cursor < self.text.len() {
-            let c = self.current();
-            if is_horizontal_whitespace(c) {
-                self.cursor += 1;
-            } else {
-                break;
+    // skip the lines covered by multi-line tokens, like block comments
+    fn skip_covered_lines(&mut self) {
+        let line_count = self.output.lines.len();
+        while usize::from(self.line_index) + 1 < line_count
+            && self.output.lines.get(self.line_index + 1).start() <= self.cursor
+        {
+            self.line_index += 1;
+            // covered lines are never entered, so they have no indentation
+            self.output.lines.get_mut(self.line_index).set_indent(0);
+        }
+    }
+
+    // the end of the current line, before its line ending
+    fn find_line_end(&mut self) -> TextSize {
+        self.skip_covered_lines();
+
+        let next_line = self.line_index + 1;
+        if usize::from(next_line) >= self.output.lines.len() {
+            return self.text.len();
+        }
+
+        let mut end = self.output.lines.get(next_line).start();
+        if end == self.text.len() && self.output.last_line_is_inserted {
+            return end;
+        }
+
+        let accepts_cr = self.options.line_endings != LineEndingPolicy::Reject;
+        match self.text.get_byte(end - 1) {
+            b'\n' => {
+                end = end - 1;
+                if end > self.cursor && self.text.get_byte(end - 1) == b'\r' {
+                    end = end - 1;
+                }
+            }
+            b'\r' if accepts_cr => {
+                end = end - 1;
+                if end > self.cursor && self.text.get_byte(end - 1) == b'\n' {
+                    end = end - 1;
+                }
             }
+            _ => {}
         }
+        end
+    }
+
+    fn skip_horizontal_whitespace(&mut self) {
+        self.cursor += horizontal_whitespace_len(self.text.get_slice(self.cursor..));
     }
 
     fn lex_horizontal_whitespace(&mut self) {
+        let start = self.cursor;
         self.has_leading_space = true;
         self.skip_horizontal_whitespace();
+        self.add_trivia(TokenKind::Whitespace, start, self.cursor);
     }
 
     fn lex_vertical_whitespace(&mut self) {
@@ -455,43 +1027,117 @@ impl<'a> Lexer<'a> {
 
     fn lex_cr(&mut self) {
         // normalize CR+LF
-        if self.peek() == b'\n' {
+        if self.peek() == b'\n' || self.options.line_endings != LineEndingPolicy::Reject {
             self.lex_vertical_whitespace();
             return;
         }
 
         let is_lfcr = self.cursor.value() > 0 && self.text.get_byte(self.cursor - 1) == b'\n';
 
-        if is_lfcr {
-            self.output
-                .diagnostics
-                .push("the LF+CR line ending is not supported, only LF and CR+LF are supported");
+        let message = if is_lfcr {
+            "the LF+CR line ending is not supported, only LF and CR+LF are supported"
         } else {
-            self.output
-                .diagnostics
-                .push("a raw CR line ending is not supported, only LF and CR+LF are supported");
-        }
+            "a raw CR line ending is not supported, only LF and CR+LF are supported"
+        };
+        self.report(message, self.cursor, self.cursor + 1);
 
         // treat unexpected CR as horizontal whitespace
         self.has_leading_space = true;
         self.cursor += 1;
+        self.add_trivia(TokenKind::Whitespace, self.cursor - 1, self.cursor);
     }
 
     fn lex_keyword_or_identifier(&mut self) {
         let start = self.cursor;
-        if self.text.get_byte(start) > 0x7F {
-            self.lex_error();
+        self.cursor += scan_identifier(self.text.get_slice(self.cursor..));
+
+        // keywords are all ASCII, but a non-ASCII character that can't continue an
+        // identifier, like a non-breaking space, still ends one
+        if self.cursor < self.text.len()
+            && !self.current().is_ascii()
+            && let Ok((c, len)) = decode_utf8_char(self.text.get_slice(self.cursor..))
+            && is_unicode_identifier_continue(c)
+        {
+            self.cursor += len;
+            self.lex_unicode_identifier_tail(start);
             return;
         }
 
-        self.cursor += scan_identifier(self.text.get_slice(self.cursor..));
         let slice = self.text.get_slice(start..self.cursor);
 
-        let kind = Self::match_keyword(slice);
-        self.add_token(kind, start);
+        let kind = Self::match_keyword(slice, self.options.language_version);
+        if self.options.language_version >= LanguageVersion::Gms23
+            && let Some(message) = Self::deprecated_keyword_message(slice)
+        {
+            self.output
+                .diagnostics
+                .push(ParseDiagnostic::warning(message, start, self.cursor));
+        }
+        if kind == TokenKind::Identifier {
+            self.add_identifier_token(kind, start);
+        } else {
+            self.add_token(kind, start);
+        }
+    }
+
+    fn lex_unicode(&mut self) {
+        let start = self.cursor;
+        match decode_utf8_char(self.text.get_slice(start..)) {
+            Ok((c, len)) if is_unicode_identifier_start(c) => {
+                self.cursor += len;
+                self.lex_unicode_identifier_tail(start);
+            }
+            Ok((_, len)) => {
+                self.lex_malformed_literal(len, "unexpected character");
+            }
+            Err(len) => {
+                self.lex_malformed_literal(len, "invalid UTF-8 sequence");
+            }
+        }
     }
 
-    fn match_keyword(text: &[u8]) -> TokenKind {
+    // Alternates between the ASCII fast path and decoding non-ASCII characters until
+    // the identifier ends.
+    fn lex_unicode_identifier_tail(&mut self, start: TextSize) {
+        while self.cursor < self.text.len() {
+            self.cursor += scan_identifier(self.text.get_slice(self.cursor..));
+            if self.cursor == self.text.len() || self.current().is_ascii() {
+                break;
+            }
+
+            match decode_utf8_char(self.text.get_slice(self.cursor..)) {
+                Ok((c, len)) if is_unicode_identifier_continue(c) => self.cursor += len,
+                _ => break,
+            }
+        }
+
+        self.add_identifier_token(TokenKind::Identifier, start);
+    }
+
+    fn add_identifier_token(&mut self, kind: TokenKind, start: TextSize) {
+        let payload = match self.symbols.as_deref_mut() {
+            Some(symbols) => {
+                let id = symbols
+                    .identifiers
+                    .push(self.text.get_span(start, self.cursor));
+                self.id_to_payload(id.into(), start)
+            }
+            None => 0,
+        };
+
+        self.add_token_with_payload(kind, payload, start);
+    }
+
+    fn match_keyword(text: &[u8], version: LanguageVersion) -> TokenKind {
+        let kind = Self::match_gms23_keyword(text);
+        if version < LanguageVersion::Gms23 && is_gms23_keyword(kind) {
+            TokenKind::Identifier
+        } else {
+            kind
+        }
+    }
+
+    fn match_gms23_keyword(text: &[u8]) -> TokenKind {
         match text {
             b"and" => TokenKind::And,
             b"or" => TokenKind::Or,
@@ -530,10 +1176,20 @@ impl<'a> Lexer<'a> {
             b"delete" => TokenKind::Delete,
             b"enum" => TokenKind::Enum,
             b"constructor" => TokenKind::Constructor,
+            b"static" => TokenKind::Static,
             _ => TokenKind::Identifier,
         }
     }
 
+    // Pascal-style block keywords still work in 2.3 and later, but braces should be used
+    fn deprecated_keyword_message(text: &[u8]) -> Option<&'static str> {
+        match text {
+            b"begin" => Some("'begin' is deprecated, use '{' instead"),
+            b"end" => Some("'end' is deprecated, use '}' instead"),
+            _ => None,
+        }
+    }
+
     fn lex_accessor(&mut self) {
         debug_assert!(self.current() == b'[');
 
@@ -563,7 +1219,6 @@ impl<'a> Lexer<'a> {
         self.cursor += 1;
         match self.current() {
             b'<' => {
-                self.cursor += 1;
                 self.lex_byte_and_equals(start, TokenKind::LeftShift, TokenKind::LeftShiftAssign);
             }
             b'=' => {
@@ -583,7 +1238,6 @@ impl<'a> Lexer<'a> {
         self.cursor += 1;
         match self.current() {
             b'>' => {
-                self.cursor += 1;
                 self.lex_byte_and_equals(start, TokenKind::RightShift, TokenKind::RightShiftAssign);
             }
             b'=' => {
@@ -601,7 +1255,6 @@ impl<'a> Lexer<'a> {
         self.cursor += 1;
         match self.current() {
             b'?' => {
-                self.cursor += 1;
                 self.lex_byte_and_equals(
                     start,
                     TokenKind::NullCoalesce,
@@ -609,12 +1262,26 @@ impl<'a> Lexer<'a> {
                 );
             }
             _ => {
-                self.add_token(TokenKind::GreaterThan, start);
+                self.add_token(TokenKind::QuestionMark, start);
             }
         }
     }
 
     fn lex_number_literal_or_dot(&mut self) {
+        if self.current() == b'0' {
+            match self.peek() {
+                b'x' => {
+                    self.lex_hex_literal(2);
+                    return;
+                }
+                b'b' => {
+                    self.lex_binary_literal();
+                    return;
+                }
+                _ => {}
+            }
+        }
+
         let start = self.cursor;
         let (len, kind) = scan_number_or_dot(self.text.get_slice(start..));
 
@@ -630,7 +1297,37 @@ impl<'a> Lexer<'a> {
             return;
         }
 
-        self.add_token_with_payload(kind, 0, start);
+        self.add_number_token(kind, start);
+    }
+
+    fn add_number_token(&mut self, kind: TokenKind, start: TextSize) {
+        let text = self.text.get_slice(start..self.cursor);
+        let (value, diagnostic) = parse_number_literal(kind, text, start);
+        self.output.diagnostics.extend(diagnostic);
+
+        let payload = match self.symbols.as_deref_mut() {
+            Some(symbols) => {
+                let id = symbols.number_literals.push(value);
+                self.id_to_payload(id.into(), start)
+            }
+            None => 0,
+        };
+
+        self.add_token_with_payload(kind, payload, start);
+    }
+
+    // Ids are stored one higher, so that a payload of 0 means the token has no id, either
+    // because the text was lexed without symbols or because the id didn't fit.
+    fn id_to_payload(&mut self, id: usize, start: TextSize) -> u32 {
+        if id + 1 >= Token::MAX_INDEX {
+            self.report(
+                "too many unique symbols to store in a token",
+                start,
+                self.cursor,
+            );
+            return 0;
+        }
+        id as u32 + 1
     }
 
     fn lex_string_literal(&mut self) {
@@ -638,7 +1335,9 @@ impl<'a> Lexer<'a> {
         let (len, kind) = scan_string_literal(self.text.get_slice(start..));
 
         if kind == TokenKind::Error {
-            self.lex_error();
+            self.report("unterminated string literal", start, start + 1);
+            self.cursor += len;
+            self.add_error_token(start, TextSize::from(len));
             return;
         }
 
@@ -649,21 +1348,113 @@ impl<'a> Lexer<'a> {
 
     fn lex_verbatim_string_literal(&mut self) {
         let start = self.cursor;
-        let (len, kind) = scan_verbatim_string_literal(self.text.get_slice(start..));
 
-        if kind == TokenKind::Error {
-            self.lex_error();
+        if !matches!(self.peek(), b'"' | b'\'') {
+            self.cursor += 1;
+            self.report("expected a string after '@'", start, self.cursor);
+            self.add_token(TokenKind::AtSign, start);
             return;
         }
 
+        let quote_style = QuoteStyle::from_byte(self.peek());
+        let (len, kind) = scan_verbatim_string_literal(self.text.get_slice(start..));
         self.cursor += len;
 
-        self.add_token_with_payload(kind, 0, start);
+        if kind == TokenKind::Error {
+            // verbatim strings can span lines, so the string runs to the end of the file
+            self.report("unterminated verbatim string literal", start, start + 2);
+        }
+
+        self.add_token_with_payload(TokenKind::VerbatimStringLiteral, quote_style as u32, start);
     }
 
     fn lex_template_string_or_hex_literal(&mut self) {
         debug_assert!(self.current() == b'$');
-        todo!("lex template strings and hex literals");
+        if self.peek() == b'"' {
+            self.lex_template_string();
+        } else {
+            self.lex_hex_literal(1);
+        }
+    }
+
+    fn lex_template_string(&mut self) {
+        let start = self.cursor;
+        let (len, stop) = scan_template_segment(self.text.get_slice(start..), 2);
+        self.cursor += len;
+
+        match stop {
+            TemplateStop::Interpolation => {
+                let token_index = self.add_token(TokenKind::TemplateStart, start);
+                self.handle_open_delimiter(token_index);
+            }
+            TemplateStop::Quote => {
+                self.add_token(TokenKind::SimpleTemplateString, start);
+            }
+            TemplateStop::Unterminated => {
+                self.report("unterminated template string", start, start + 2);
+                self.add_token(TokenKind::SimpleTemplateString, start);
+            }
+        }
+    }
+
+    // Template segments are linked like delimiters: each segment stores the index of the
+    // segment that follows it, and the final segment stores the index of the one before.
+    fn lex_template_continuation(&mut self) {
+        debug_assert!(self.current() == b'}');
+        let start = self.cursor;
+        let (len, stop) = scan_template_segment(self.text.get_slice(start..), 1);
+        self.cursor += len;
+
+        match stop {
+            TemplateStop::Interpolation => {
+                let token_index = self.add_token(TokenKind::TemplateMiddle, start);
+                self.handle_close_delimiter(token_index);
+                self.handle_open_delimiter(token_index);
+            }
+            TemplateStop::Quote => {
+                let token_index = self.add_token(TokenKind::TemplateEnd, start);
+                self.handle_close_delimiter(token_index);
+            }
+            TemplateStop::Unterminated => {
+                self.report("unterminated template string", start, start + 1);
+                let token_index = self.add_token(TokenKind::TemplateEnd, start);
+                self.handle_close_delimiter(token_index);
+            }
+        }
+    }
+
+    fn lex_hex_literal(&mut self, prefix_len: usize) {
+        let start = self.cursor;
+        let (len, kind) = scan_hex_literal(self.text.get_slice(start..), prefix_len);
+
+        if kind == TokenKind::Error {
+            self.lex_malformed_literal(len, "malformed hexadecimal literal");
+            return;
+        }
+
+        self.cursor += len;
+        self.add_number_token(kind, start);
+    }
+
+    fn lex_binary_literal(&mut self) {
+        let start = self.cursor;
+        let (len, kind) = scan_binary_literal(self.text.get_slice(start..));
+
+        if kind == TokenKind::Error {
+            self.lex_malformed_literal(len, "malformed binary literal");
+            return;
+        }
+
+        self.cursor += len;
+        self.add_number_token(kind, start);
+    }
+
+    // covers `len` bytes with an error token
+    fn lex_malformed_literal(&mut self, len: usize, message: &'static str) {
+        let start = self.cursor;
+        self.cursor += len;
+        self.report(message, start, self.cursor);
+        self.add_error_token(start, TextSize::from(len));
     }
 
     fn lex_comment_or_divide(&mut self) {
@@ -672,37 +1463,152 @@ impl<'a> Lexer<'a> {
 
         match self.peek() {
             b'/' => {
-                self.advance_to_next_line();
-                self.output.add_comment(Comment::new(start, self.cursor));
+                // the line break is lexed separately, so that it's trivia of its own
+                let end = self.find_line_end();
+                self.cursor = end;
+
+                // `///` starts a doc comment, but `////` is a regular comment
+                let slice = self.text.get_slice(start..end);
+                let kind = if slice.starts_with(b"///") && !slice.starts_with(b"////") {
+                    TokenKind::DocComment
+                } else {
+                    TokenKind::SingleLineComment
+                };
+                self.add_trivia(kind, start, end);
             }
             b'*' => {
-                self.cursor += 1;
-                while self.cursor < self.text.len() {
-                    while self.cursor + 1 < self.text.len() {
-                        self.cursor += 1;
-                        if self.current() == b'*' {
-                            self.cursor += 1;
-                            break;
-                        }
-                    }
-                    if self.current() == b'/' {
-                        self.cursor += 1;
-                        self.output.add_comment(Comment::new(start, self.cursor));
-                        break;
+                let body_start = start + 2;
+                match find_comment_end(self.text.get_slice(body_start..)) {
+                    Some(end) => self.cursor = body_start + end + 2,
+                    None => {
+                        self.cursor = self.text.len();
+                        self.report("unterminated multi-line comment", start, start + 2);
                     }
                 }
+                self.add_trivia(TokenKind::MultiLineComment, start, self.cursor);
             }
             _ => self.lex_byte_and_equals(start, TokenKind::Divide, TokenKind::DivideAssign),
         }
     }
 
+    fn lex_hash(&mut self) {
+        debug_assert!(self.current() == b'#');
+        let start = self.cursor;
+        let name_start = start + 1;
+        let name_len = scan_identifier(self.text.get_slice(name_start..));
+        let name = self.text.get_slice(name_start..name_start + name_len);
+        let is_color_literal = name.len() == 6 && name.iter().all(u8::is_ascii_hexdigit);
+
+        self.cursor = name_start + name_len;
+
+        if is_color_literal {
+            self.add_number_token(TokenKind::HexIntegerLiteral, start);
+            return;
+        }
+
+        match name {
+            b"macro" => self.lex_macro(start),
+            b"region" => self.lex_region(TokenKind::Region, start),
+            b"endregion" => self.lex_region(TokenKind::EndRegion, start),
+            b"define" => {
+                self.add_token(TokenKind::Define, start);
+            }
+            _ => {
+                self.report("unknown preprocessor directive", start, self.cursor);
+                self.add_token(TokenKind::UnknownDirective, start);
+            }
+        }
+    }
+
+    // `#macro [config:]name body` where the body runs to the end of the line, or further
+    // if the line ends with a `\`. The body is introduced by an empty `MacroBody` token
+    // whose payload is the number of tokens in the body.
+    fn lex_macro(&mut self, start: TextSize) {
+        self.add_token(TokenKind::Macro, start);
+        self.lex_horizontal_whitespace();
+
+        if !self.lex_macro_name() {
+            self.report("expected a macro name", self.cursor, self.cursor);
+        } else if self.current() == b':' && is_identifier_start(self.peek()) {
+            // the first name was a configuration, e.g. `#macro Release:DEBUG false`
+            self.lex_byte(TokenKind::Colon);
+            self.lex_macro_name();
+        }
+
+        let body_index = self.add_token(TokenKind::MacroBody, self.cursor);
+        self.macro_body = Some(body_index);
+    }
+
+    fn lex_macro_name(&mut self) -> bool {
+        let start = self.cursor;
+        if start >= self.text.len() || !is_identifier_start(self.current()) {
+            return false;
+        }
+
+        self.cursor += scan_identifier(self.text.get_slice(start..));
+        self.add_identifier_token(TokenKind::MacroName, start);
+        true
+    }
+
+    fn end_macro_body_at_line_break(&mut self) {
+        let Some(body_index) = self.macro_body else {
+            return;
+        };
+
+        let last_index = TokenIndex::from(self.output.token_count() - 1);
+        if last_index != body_index && self.output.get_kind(last_index) == TokenKind::Backslash {
+            // line continuation
+            return;
+        }
+
+        self.finish_macro_body();
+    }
+
+    fn finish_macro_body(&mut self) {
+        if let Some(body_index) = self.macro_body.take() {
+            let last_index = TokenIndex::from(self.output.token_count() - 1);
+            let body_token = self.output.tokens.get_mut(body_index);
+            body_token.set_payload((last_index - body_index).value());
+        }
+    }
+
+    fn lex_backslash(&mut self) {
+        if self.macro_body.is_some() {
+            self.lex_byte(TokenKind::Backslash);
+        } else {
+            self.lex_error();
+        }
+    }
+
+    // the name of a region is the rest of the line, without trailing whitespace
+    fn lex_region(&mut self, kind: TokenKind, start: TextSize) {
+        self.add_token(kind, start);
+        self.lex_horizontal_whitespace();
+
+        let mut name_end = self.find_line_end();
+        while name_end > self.cursor
+            && matches!(self.text.get_byte(name_end - 1), b' ' | b'\t' | b'\r')
+        {
+            name_end = name_end - 1;
+        }
+
+        if name_end > self.cursor {
+            self.add_token(TokenKind::RegionName, self.cursor);
+            self.cursor = name_end;
+        }
+    }
+
     fn lex_error(&mut self) {
         // keep lexing until we hit a recovery character
         let start = self.cursor;
 
         while self.cursor < self.text.len() {
             let c = self.current();
-            if is_identifier_byte(c) || is_horizontal_whitespace(c) {
+            if is_identifier_byte(c)
+                || is_horizontal_whitespace(c)
+                || matches!(c, b'\n' | b'\r')
+                || !c.is_ascii()
+            {
                 break;
             }
             self.cursor += 1;
@@ -716,10 +1622,932 @@ impl<'a> Lexer<'a> {
             len += 1;
         }
 
-        self.output
+        self.report("unrecognized characters while parsing", start, self.cursor);
+        self.add_error_token(start, len);
+    }
+
+    // Everything from the start of a line before the edit is lexed again, until the lexer
+    // reaches a line after the edit in the same state as before. The new tokens are added
+    // after the old ones, then moved in place of the ones they replace, and the old tokens
+    // from that line on are shifted to their new positions where they are.
+    fn relex(&mut self, edit: TextEdit) {
+        let (line, token, trivia) = Self::find_restart(&self.output, edit.range.start());
+        let line_start = self.output.lines.get(line).start();
+
+        self.output.diagnostics.retain(|diagnostic| {
+            !is_delimiter_error(diagnostic.message) && !is_suppression_error(diagnostic.message)
+        });
+        let (diagnostics, kept) = std::mem::take(&mut self.output.diagnostics)
+            .into_iter()
+            .partition(|diagnostic| diagnostic.range.start() >= line_start);
+        self.output.diagnostics = kept;
+
+        let (old_lines, lines_end) = self.relex_lines(edit, line);
+        let open_payloads = self.restore_delimiters(token);
+        let errors = &mut self.output.delimiter_errors;
+        let delimiter_errors =
+            errors.split_off(errors.partition_point(|error| error.found_at <= token.into()));
+        let snapshots = &mut self.output.delimiter_snapshots;
+        let delimiter_snapshots =
+            snapshots.split_off(snapshots.partition_point(|snapshot| snapshot.token <= token));
+
+        let restart = Restart {
+            token,
+            trivia,
+            new_tokens: TokenIndex::from(self.output.token_count()),
+            new_trivia: TriviaIndex::from(self.output.trivia_count()),
+            old_lines,
+            lines_end,
+            open_payloads,
+            diagnostics,
+            delimiter_errors,
+            delimiter_snapshots,
+        };
+        self.window_start = restart.new_tokens;
+        self.window_shift = usize::from(restart.new_tokens) - usize::from(token);
+
+        if token == 0 {
+            self.lex_file_start();
+        } else {
+            self.line_index = line - 1;
+            self.has_leading_space = true;
+            self.advance_to_line(line);
+        }
+
+        let mut resync = Resync {
+            edit,
+            token,
+            end: restart.new_tokens,
+            open_delimiters: self.open_delimiters.clone(),
+        };
+
+        while self.cursor < self.text.len() {
+            if self.token_count() >= TOKEN_LIMIT {
+                self.lex_token_limit_exceeded();
+                break;
+            }
+
+            self.entered_line = false;
+            self.lex_next();
+
+            if self.entered_line
+                && let Some(tail) = self.try_resync(&mut resync)
+            {
+                self.replace_tokens(restart, &resync, Some(tail), edit);
+                return;
+            }
+        }
+
+        self.lex_file_end();
+        self.close_unclosed_delimiters();
+        self.replace_tokens(restart, &resync, None, edit);
+    }
+
+    // The restart line has to be entered after a line break, rather than covered by a
+    // multi-line token or continuing a macro. Line breaks are classified by looking up to
+    // two bytes past them, so the line has to start at least that far before the edit.
+    // Returns the line, its first token, and the first trivia after its line break.
+    fn find_restart(
+        tokens: &TokenizedText,
+        edit_start: TextSize,
+    ) -> (LineIndex, TokenIndex, TriviaIndex) {
+        let mut line = tokens.find_line_index(edit_start);
+        while line > 0 {
+            let line_start = tokens.lines.get(line).start();
+            if line_start + 2 <= edit_start {
+                let token = Self::find_token_at(tokens, line_start);
+                if let Some(line_break) = Self::find_line_break(tokens, token, line_start)
+                    && tokens.get_kind(token - 1) != TokenKind::Backslash
+                {
+                    return (line, token, line_break + 1);
+                }
+            }
+            line = line - 1;
+        }
+        (
+            LineIndex::from(0),
+            TokenIndex::from(0),
+            TriviaIndex::from(0),
+        )
+    }
+
+    // the first token that starts at or after `offset`
+    fn find_token_at(tokens: &TokenizedText, offset: TextSize) -> TokenIndex {
+        let mut left = 0;
+        let mut right = tokens.token_count();
+        while left < right {
+            let mid = (left + right) / 2;
+            if tokens.get_start(mid.into()) < offset {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        TokenIndex::from(left)
+    }
+
+    // the line break before `token` that ends at `line_start`
+    fn find_line_break(
+        tokens: &TokenizedText,
+        token: TokenIndex,
+        line_start: TextSize,
+    ) -> Option<TriviaIndex> {
+        let (first, last) = tokens.trivia_before(token);
+        (usize::from(first)..usize::from(last))
+            .map(TriviaIndex::from)
+            .find(|&index| {
+                let trivia = tokens.get_trivia(index);
+                trivia.kind() == TokenKind::LineBreak && trivia.end() == line_start
+            })
+    }
+
+    // Finds the lines from the restart line on again, up to the first line after the edit
+    // that is found the same way as before. The lines after it are shifted in place.
+    // Returns the old lines that were replaced, and the end of the new ones.
+    fn relex_lines(&mut self, edit: TextEdit, line: LineIndex) -> (Vec<Line>, LineIndex) {
+        let accepts_cr = self.options.line_endings != LineEndingPolicy::Reject;
+        let line_start = self.output.lines.get(line).start();
+        let mut scan_start = line_start;
+        if line > 0
+            && !accepts_cr
+            && self.output.lines.get(line - 1).ending() == Some(LineEnding::LfCr)
+        {
+            scan_start += 1;
+        }
+
+        let mut new_lines = Vec::new();
+        let end = Self::scan_lines(
+            self.text,
+            accepts_cr,
+            line_start,
+            scan_start,
+            Some(edit.new_range().end()),
+            |line| new_lines.push(line),
+        );
+
+        let old_end = match end {
+            LinesEnd::Resync(start) => {
+                let old_line = self.output.find_line_index(edit.unshift(start));
+                debug_assert!(self.output.lines.get(old_line).start() == edit.unshift(start));
+                old_line
+            }
+            LinesEnd::FileEnd {
+                last_line_is_inserted,
+            } => {
+                self.output.last_line_is_inserted = last_line_is_inserted;
+                LineIndex::from(self.output.lines.len())
+            }
+        };
+
+        let old_lines = self.output.replace_lines(line..old_end, &new_lines, edit);
+        Self::record_line_ending(&mut self.output, self.options.line_endings);
+        (old_lines, line + new_lines.len())
+    }
+
+    // Rebuilds the delimiters that are open before `token` from the last snapshot before
+    // it, rather than following every delimiter from the start of the file. Their payloads
+    // are reset, since the tokens that closed them are lexed again, and the old ones are
+    // returned.
+    fn restore_delimiters(&mut self, token: TokenIndex) -> Vec<(TokenIndex, u32)> {
+        if token == 0 {
+            return Vec::new();
+        }
+
+        let snapshots = &self.output.delimiter_snapshots;
+        let snapshot =
+            &snapshots[snapshots.partition_point(|snapshot| snapshot.token <= token) - 1];
+        let mut open_delimiters = snapshot.open_delimiters.clone();
+        for index in usize::from(snapshot.token)..usize::from(token) {
+            follow_delimiters(&self.output, &mut open_delimiters, index.into());
+        }
+        // the restart line follows a line break too
+        end_interpolations(&self.output, &mut open_delimiters);
+
+        let open_payloads = open_delimiters
+            .iter()
+            .map(|&open| {
+                let open_token = self.output.tokens.get_mut(open);
+                let payload = open_token.payload();
+                open_token.set_payload(0);
+                (open, payload)
+            })
+            .collect();
+        self.open_delimiters = open_delimiters;
+        open_payloads
+    }
+
+    // Checks whether the old tokens can be reused from the line that was just entered, and
+    // returns the first old token and trivia to reuse.
+    fn try_resync(&mut self, resync: &mut Resync) -> Option<(TokenIndex, TriviaIndex)> {
+        let line_start = self.output.lines.get(self.line_index).start();
+        if line_start < resync.edit.new_range().end() || self.macro_body.is_some() {
+            return None;
+        }
+
+        let old_line_start = resync.edit.unshift(line_start);
+        let old_cursor = resync.edit.unshift(self.cursor);
+        while resync.token < resync.end && self.output.get_start(resync.token) < old_cursor {
+            resync.advance(&self.output);
+        }
+
+        let line_break = Self::find_line_break(&self.output, resync.token, old_line_start)?;
+        end_interpolations(&self.output, &mut resync.open_delimiters);
+        let same_delimiters = resync.open_delimiters.len() == self.open_delimiters.len()
+            && resync
+                .open_delimiters
+                .iter()
+                .zip(&self.open_delimiters)
+                .all(|(&old, &new)| self.output.get_kind(old) == self.output.get_kind(new));
+        let continues_macro =
+            resync.token > 0 && self.output.get_kind(resync.token - 1) == TokenKind::Backslash;
+        if continues_macro || !same_delimiters {
+            return None;
+        }
+
+        // the indentation was lexed again, and is the same as before
+        let mut trivia = line_break + 1;
+        if self.cursor > line_start {
+            trivia += 1;
+        }
+        Some((resync.token, trivia))
+    }
+
+    // Moves the new tokens in place of the old ones they replace. With `tail`, the old
+    // tokens and trivia from there on are reused: they're shifted by the edit, and the
+    // delimiters that were open at the resync line stand in for the old ones in their
+    // payloads and errors.
+    fn replace_tokens(
+        &mut self,
+        restart: Restart,
+        resync: &Resync,
+        tail: Option<(TokenIndex, TriviaIndex)>,
+        edit: TextEdit,
+    ) {
+        let (tail_token, tail_trivia) = tail.unwrap_or((restart.new_tokens, restart.new_trivia));
+        let new_tail_token = TokenIndex::from(self.token_count());
+        let old_open = if tail.is_some() {
+            resync.open_delimiters.as_slice()
+        } else {
+            &[]
+        };
+        let new_open: Vec<TokenIndex> = self
+            .open_delimiters
+            .iter()
+            .map(|&token| self.final_index(token))
+            .collect();
+
+        // the delimiters that are still open were closed by the old tail
+        for (&old, &new) in old_open.iter().zip(&self.open_delimiters) {
+            let payload = if old < restart.token {
+                let index = restart
+                    .open_payloads
+                    .binary_search_by_key(&old, |&(token, _)| token)
+                    .expect("an old delimiter that is still open was open before the edit");
+                restart.open_payloads[index].1
+            } else {
+                self.output.tokens.get(old).payload()
+            };
+            if payload >= tail_token.value() {
+                let payload = TokenIndex::from(payload as usize) - tail_token + new_tail_token;
+                self.output.tokens.get_mut(new).set_payload(payload.value());
+            }
+        }
+
+        let map_token = |token: TokenIndex| {
+            if token >= tail_token {
+                token - tail_token + new_tail_token
+            } else {
+                let index = old_open
+                    .binary_search(&token)
+                    .expect("the old tail only refers to delimiters open before it");
+                new_open[index]
+            }
+        };
+        self.output.replace(
+            restart.token..tail_token,
+            restart.trivia..tail_trivia,
+            restart.new_tokens,
+            restart.new_trivia,
+            edit,
+            map_token,
+        );
+
+        if tail.is_some() {
+            // lines after the resync line that were found again keep their old indentation
+            for line in usize::from(self.line_index) + 1..usize::from(restart.lines_end) {
+                let line = LineIndex::from(line);
+                let old_start = edit.unshift(self.output.lines.get(line).start());
+                if let Ok(index) = restart
+                    .old_lines
+                    .binary_search_by_key(&old_start, |line| line.start())
+                {
+                    let indent = restart.old_lines[index].indent();
+                    self.output.lines.get_mut(line).set_indent(indent);
+                }
+            }
+
+            let old_line_start = edit.unshift(self.output.lines.get(self.line_index).start());
+            self.output.diagnostics.extend(
+                restart
+                    .diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.range.start() >= old_line_start)
+                    .map(|diagnostic| ParseDiagnostic {
+                        range: TextRange::new(
+                            edit.shift(diagnostic.range.start()),
+                            edit.shift(diagnostic.range.end()),
+                        ),
+                        ..*diagnostic
+                    }),
+            );
+        } else {
+            // the lines after the last one that was lexed were never entered
+            for line in usize::from(self.line_index) + 1..self.output.lines.len() {
+                self.output.lines.get_mut(line.into()).set_indent(0);
+            }
+        }
+
+        let found_after = usize::from(tail_token);
+        let shift_found = |found_at: usize| found_at - found_after + usize::from(new_tail_token);
+        self.output.delimiter_errors.extend(
+            restart
+                .delimiter_errors
+                .into_iter()
+                .filter(|error| error.found_at > found_after)
+                .map(|error| DelimiterError {
+                    token: map_token(error.token),
+                    found_at: shift_found(error.found_at),
+                    ..error
+                }),
+        );
+        self.output.delimiter_snapshots.extend(
+            restart
+                .delimiter_snapshots
+                .into_iter()
+                .filter(|snapshot| snapshot.token > tail_token)
+                .map(|snapshot| DelimiterSnapshot {
+                    token: map_token(snapshot.token),
+                    open_delimiters: snapshot
+                        .open_delimiters
+                        .into_iter()
+                        .map(map_token)
+                        .collect(),
+                }),
+        );
+
+        self.report_delimiter_errors();
+        self.read_suppressions();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Severity;
+    use crate::user_symbols::NumberValue;
+
+    fn kinds(tokens: &TokenizedText) -> Vec<TokenKind> {
+        (0..tokens.token_count())
+            .map(|index| tokens.get_kind(index.into()))
+            .collect()
+    }
+
+    // every token between FileStart and FileEnd, with its text
+    fn lexemes<'a>(text: &'a str, tokens: &TokenizedText) -> Vec<(TokenKind, &'a str)> {
+        (1..tokens.token_count() - 1)
+            .map(|index| {
+                let start = usize::from(tokens.get_start(index.into()));
+                let end = usize::from(tokens.get_end(index.into()));
+                (tokens.get_kind(index.into()), &text[start..end])
+            })
+            .collect()
+    }
+
+    fn messages(tokens: &TokenizedText) -> Vec<(&'static str, TextRange)> {
+        tokens
             .diagnostics
-            .push("unrecognized characters while parsing");
+            .iter()
+            .map(|diagnostic| (diagnostic.message, diagnostic.range))
+            .collect()
+    }
+
+    fn range(start: usize, end: usize) -> TextRange {
+        TextRange::new(start.into(), end.into())
+    }
 
-        self.add_token_with_payload(TokenKind::Error, len.value(), start);
+    #[test]
+    fn keyword_before_non_identifier_character() {
+        let tokens = lex(&SourceText::from_str("if\u{a0}(x) {}"));
+        assert_eq!(tokens.get_kind(1.into()), TokenKind::If);
+
+        let tokens = lex(&SourceText::from_str("return\u{3000}x;"));
+        assert_eq!(tokens.get_kind(1.into()), TokenKind::Return);
+    }
+
+    #[test]
+    fn keyword_followed_by_identifier_character() {
+        let tokens = lex(&SourceText::from_str("ifé = 1;"));
+        assert_eq!(tokens.get_kind(1.into()), TokenKind::Identifier);
+        assert_eq!(tokens.get_end(1.into()), TextSize::from(4));
+        assert_eq!(kinds(&tokens).last(), Some(&TokenKind::FileEnd));
+    }
+
+    // every input is lexed to the end, with its problems reported as diagnostics
+    #[test]
+    fn malformed_input_is_reported() {
+        let cases: &[(&str, &str, usize)] = &[
+            ("@", "expected a string after '@'", 1),
+            ("$", "malformed hexadecimal literal", 1),
+            ("0b", "malformed binary literal", 2),
+            ("0x", "malformed hexadecimal literal", 2),
+            ("0xFG", "malformed hexadecimal literal", 4),
+            ("/*", "unterminated multi-line comment", 2),
+            ("\"", "unterminated string literal", 1),
+            (
+                "\r",
+                "a raw CR line ending is not supported, only LF and CR+LF are supported",
+                1,
+            ),
+        ];
+        for &(text, message, end) in cases {
+            let tokens = lex(&SourceText::from_str(text));
+            assert_eq!(kinds(&tokens).last(), Some(&TokenKind::FileEnd), "{text:?}");
+            let diagnostics: Vec<_> = tokens
+                .diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.message, diagnostic.range))
+                .collect();
+            assert_eq!(
+                diagnostics,
+                [(message, TextRange::new(0.into(), end.into()))],
+                "{text:?}"
+            );
+        }
+    }
+
+    // A source text is always valid UTF-8, so a truncated sequence is rejected before it
+    // is lexed, and the lexer reports the bytes it can't decode as a whole.
+    #[test]
+    fn truncated_utf8_sequence() {
+        let bytes = b"x = \xE2\x82";
+        assert!(matches!(
+            SourceText::try_from_slice(bytes),
+            Err(crate::source_text::SourceError::InvalidUtf8 { offset: 4 })
+        ));
+        assert_eq!(decode_utf8_char(&bytes[4..]), Err(2));
+    }
+
+    #[test]
+    fn template_string_with_interpolations() {
+        let text = r#"$"HP: {hp}/{max_hp}""#;
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::TemplateStart, "$\"HP: {"),
+                (TokenKind::Identifier, "hp"),
+                (TokenKind::TemplateMiddle, "}/{"),
+                (TokenKind::Identifier, "max_hp"),
+                (TokenKind::TemplateEnd, "}\""),
+            ]
+        );
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn nested_template_strings() {
+        let text = r#"$"a{$"b{c}"}d""#;
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::TemplateStart, "$\"a{"),
+                (TokenKind::TemplateStart, "$\"b{"),
+                (TokenKind::Identifier, "c"),
+                (TokenKind::TemplateEnd, "}\""),
+                (TokenKind::TemplateEnd, "}d\""),
+            ]
+        );
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    // a `}` only resumes the template when no brace is open inside the interpolation
+    #[test]
+    fn struct_literal_inside_interpolation() {
+        let text = r#"$"{ {x: 1} }""#;
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::TemplateStart, "$\"{"),
+                (TokenKind::LeftBrace, "{"),
+                (TokenKind::Identifier, "x"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::IntegerLiteral, "1"),
+                (TokenKind::RightBrace, "}"),
+                (TokenKind::TemplateEnd, "}\""),
+            ]
+        );
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    // the interpolation ends at the line break, so the `}` on the next line is a brace
+    #[test]
+    fn interpolation_unclosed_at_line_break() {
+        let text = "s = $\"a{b\n}";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Identifier, "s"),
+                (TokenKind::Equals, "="),
+                (TokenKind::TemplateStart, "$\"a{"),
+                (TokenKind::Identifier, "b"),
+                (TokenKind::RightBrace, "}"),
+            ]
+        );
+        assert_eq!(
+            messages(&tokens),
+            [
+                ("unclosed template string interpolation", range(7, 8)),
+                ("closing delimiter has no opening delimiter", range(10, 11)),
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_with_configuration() {
+        let text = "#macro Release:DEBUG false";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Macro, "#macro"),
+                (TokenKind::MacroName, "Release"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::MacroName, "DEBUG"),
+                (TokenKind::MacroBody, ""),
+                (TokenKind::BooleanLiteral, "false"),
+            ]
+        );
+        assert_eq!(tokens.tokens.get(5.into()).payload(), 1);
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    // the body runs past a line that ends with `\`, and stops at the next line break
+    #[test]
+    fn macro_with_line_continuation() {
+        let text = "#macro SUM 1 + \\\n    2\nx";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Macro, "#macro"),
+                (TokenKind::MacroName, "SUM"),
+                (TokenKind::MacroBody, ""),
+                (TokenKind::IntegerLiteral, "1"),
+                (TokenKind::Plus, "+"),
+                (TokenKind::Backslash, "\\"),
+                (TokenKind::IntegerLiteral, "2"),
+                (TokenKind::Identifier, "x"),
+            ]
+        );
+        assert_eq!(tokens.tokens.get(3.into()).payload(), 4);
+    }
+
+    #[test]
+    fn region_names_are_trimmed() {
+        let text = "#region  Player movement \t\r\n#endregion\n#endregion done";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Region, "#region"),
+                (TokenKind::RegionName, "Player movement"),
+                (TokenKind::EndRegion, "#endregion"),
+                (TokenKind::EndRegion, "#endregion"),
+                (TokenKind::RegionName, "done"),
+            ]
+        );
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unknown_directive() {
+        let text = "#pragma once";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::UnknownDirective, "#pragma"),
+                (TokenKind::Identifier, "once"),
+            ]
+        );
+        assert_eq!(
+            messages(&tokens),
+            [("unknown preprocessor directive", range(0, 7))]
+        );
+    }
+
+    // six hex digits after `#` are a color, stored as `$BBGGRR`
+    #[test]
+    fn color_literal() {
+        let text = "c = #FF8000;";
+        let source = SourceText::from_str(text);
+        let mut symbols = UserSymbols::new();
+        let tokens = lex_with_symbols(&source, &mut symbols);
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Identifier, "c"),
+                (TokenKind::Equals, "="),
+                (TokenKind::HexIntegerLiteral, "#FF8000"),
+                (TokenKind::Semicolon, ";"),
+            ]
+        );
+        let id = tokens.get_number_literal_id(3.into()).unwrap();
+        assert_eq!(
+            *symbols.number_literals.get(id),
+            NumberValue::Integer(0x0080FF)
+        );
+    }
+
+    // the other quote character is plain text inside a verbatim string
+    #[test]
+    fn single_quoted_verbatim_string() {
+        let text = r#"@'He said "hi"'"#;
+        let source = SourceText::from_str(text);
+        let tokens = lex(&source);
+        assert_eq!(
+            lexemes(text, &tokens),
+            [(TokenKind::VerbatimStringLiteral, text)]
+        );
+        assert_eq!(tokens.get_quote_style(1.into()), QuoteStyle::Single);
+        let mut diagnostics = Vec::new();
+        assert_eq!(
+            tokens.get_string_value(&source, 1.into(), &mut diagnostics),
+            r#"He said "hi""#
+        );
+        assert!(diagnostics.is_empty());
+    }
+
+    // verbatim strings have no escapes, so a doubled quote ends the string
+    #[test]
+    fn verbatim_string_ends_at_first_quote() {
+        let text = r#"@"a""b""#;
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::VerbatimStringLiteral, "@\"a\""),
+                (TokenKind::StringLiteral, "\"b\""),
+            ]
+        );
+        assert_eq!(tokens.get_quote_style(1.into()), QuoteStyle::Double);
+    }
+
+    #[test]
+    fn member_starting_with_underscore() {
+        let text = "self._x = 1;";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Identifier, "self"),
+                (TokenKind::Dot, "."),
+                (TokenKind::Identifier, "_x"),
+                (TokenKind::Equals, "="),
+                (TokenKind::IntegerLiteral, "1"),
+                (TokenKind::Semicolon, ";"),
+            ]
+        );
+        assert!(tokens.diagnostics.is_empty());
+    }
+
+    // the line number of each token, and the line ending that a formatter would write
+    #[test]
+    fn line_endings_under_each_policy() {
+        use LineEndingPolicy::{Accept, Normalize, Reject};
+        const RAW_CR: &str =
+            "a raw CR line ending is not supported, only LF and CR+LF are supported";
+        const LF_CR: &str =
+            "the LF+CR line ending is not supported, only LF and CR+LF are supported";
+
+        let cases = [
+            ("a\rb\rc", Accept, [1, 2, 3], LineEnding::Cr, vec![]),
+            ("a\rb\rc", Normalize, [1, 2, 3], LineEnding::Lf, vec![]),
+            (
+                "a\rb\rc",
+                Reject,
+                [1, 1, 1],
+                LineEnding::Cr,
+                vec![(RAW_CR, range(1, 2)), (RAW_CR, range(3, 4))],
+            ),
+            ("a\n\rb\n\rc", Accept, [1, 2, 3], LineEnding::LfCr, vec![]),
+            (
+                "a\n\rb\n\rc",
+                Normalize,
+                [1, 2, 3],
+                LineEnding::CrLf,
+                vec![],
+            ),
+            // the CR after each LF is whitespace at the start of the next line
+            (
+                "a\n\rb\n\rc",
+                Reject,
+                [1, 2, 3],
+                LineEnding::LfCr,
+                vec![(LF_CR, range(2, 3)), (LF_CR, range(5, 6))],
+            ),
+        ];
+        for (text, policy, lines, line_ending, diagnostics) in cases {
+            let options = LexOptions {
+                line_endings: policy,
+                ..LexOptions::default()
+            };
+            let tokens = lex_with_options(&SourceText::from_str(text), &options, None);
+            let context = format!("{text:?} {policy:?}");
+            let token_lines: Vec<_> = (1..tokens.token_count() - 1)
+                .map(|index| tokens.get_line_number(index.into()))
+                .collect();
+            assert_eq!(token_lines, lines, "{context}");
+            assert_eq!(tokens.line_ending, line_ending, "{context}");
+            assert!(!tokens.has_mixed_line_endings, "{context}");
+            assert_eq!(messages(&tokens), diagnostics, "{context}");
+        }
+    }
+
+    #[test]
+    fn mixed_line_endings() {
+        let options = LexOptions {
+            line_endings: LineEndingPolicy::Accept,
+            ..LexOptions::default()
+        };
+        let tokens = lex_with_options(&SourceText::from_str("a\r\nb\r\nc\rd"), &options, None);
+        assert_eq!(tokens.line_ending, LineEnding::CrLf);
+        assert!(tokens.has_mixed_line_endings);
+    }
+
+    #[test]
+    fn mismatched_delimiters() {
+        let tokens = lex(&SourceText::from_str("(]"));
+        assert_eq!(
+            messages(&tokens),
+            [
+                (MISMATCHED_OPEN_DELIMITER, range(0, 1)),
+                (MISMATCHED_CLOSE_DELIMITER, range(1, 2)),
+            ]
+        );
+        assert!(tokens.has_mismatched_brackets);
+        assert_eq!(tokens.matching_delimiter(1.into()), None);
+        assert_eq!(tokens.matching_delimiter(2.into()), None);
+    }
+
+    #[test]
+    fn stray_close_delimiter() {
+        let tokens = lex(&SourceText::from_str("f(x))"));
+        assert_eq!(
+            messages(&tokens),
+            [(UNMATCHED_CLOSE_DELIMITER, range(4, 5))]
+        );
+        assert!(tokens.has_mismatched_brackets);
+        assert_eq!(tokens.matching_delimiter(2.into()), Some(4.into()));
+        assert_eq!(tokens.matching_delimiter(4.into()), Some(2.into()));
+        assert_eq!(tokens.matching_delimiter(5.into()), None);
+    }
+
+    #[test]
+    fn accessor_unclosed_at_end_of_file() {
+        let text = "a[@";
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::Identifier, "a"),
+                (TokenKind::ArrayAccessor, "[@"),
+            ]
+        );
+        assert_eq!(messages(&tokens), [(UNCLOSED_DELIMITER, range(1, 3))]);
+        assert_eq!(tokens.matching_delimiter(2.into()), None);
+    }
+
+    // each segment is matched to the one after it, and the last one back to the middle
+    #[test]
+    fn nested_template_segments_are_linked() {
+        let text = r#"$"a{b}c{$"d{e}"}f""#;
+        let tokens = lex(&SourceText::from_str(text));
+        assert_eq!(
+            lexemes(text, &tokens),
+            [
+                (TokenKind::TemplateStart, "$\"a{"),
+                (TokenKind::Identifier, "b"),
+                (TokenKind::TemplateMiddle, "}c{"),
+                (TokenKind::TemplateStart, "$\"d{"),
+                (TokenKind::Identifier, "e"),
+                (TokenKind::TemplateEnd, "}\""),
+                (TokenKind::TemplateEnd, "}f\""),
+            ]
+        );
+        let matches: Vec<_> = [1, 3, 4, 6, 7]
+            .into_iter()
+            .map(|token| tokens.matching_delimiter(token.into()).map(usize::from))
+            .collect();
+        assert_eq!(matches, [Some(3), Some(7), Some(6), Some(4), Some(3)]);
+        assert!(!tokens.has_mismatched_brackets);
+    }
+
+    // `begin` and `end` are lexed as braces, but aren't matched like them
+    #[test]
+    fn begin_and_end_have_no_matching_delimiter() {
+        let text = "if (x) begin y = 1; end";
+        let tokens = lex(&SourceText::from_str(text));
+        let kinds = kinds(&tokens);
+        let begin = kinds.iter().position(|&kind| kind == TokenKind::LeftBrace);
+        let end = kinds.iter().position(|&kind| kind == TokenKind::RightBrace);
+        let (begin, end) = (begin.unwrap(), end.unwrap());
+
+        assert_eq!(tokens.matching_delimiter(begin.into()), None);
+        assert_eq!(tokens.matching_delimiter(end.into()), None);
+        assert_eq!(tokens.matching_delimiter(2.into()), Some(4.into()));
+        assert!(!tokens.has_mismatched_brackets);
+    }
+
+    fn lex_version(text: &str, language_version: LanguageVersion) -> TokenizedText {
+        let options = LexOptions {
+            language_version,
+            ..LexOptions::default()
+        };
+        lex_with_options(&SourceText::from_str(text), &options, None)
+    }
+
+    #[test]
+    fn gms23_keywords_by_version() {
+        let cases = [
+            ("static", TokenKind::Static),
+            ("function", TokenKind::Function),
+            ("constructor", TokenKind::Constructor),
+            ("new", TokenKind::New),
+            ("delete", TokenKind::Delete),
+            ("try", TokenKind::Try),
+            ("catch", TokenKind::Catch),
+            ("finally", TokenKind::Finally),
+            ("throw", TokenKind::Throw),
+        ];
+        for (text, kind) in cases {
+            let tokens = lex_version(text, LanguageVersion::Gms22);
+            assert_eq!(lexemes(text, &tokens), [(TokenKind::Identifier, text)]);
+            let tokens = lex_version(text, LanguageVersion::Gms23);
+            assert_eq!(lexemes(text, &tokens), [(kind, text)]);
+        }
+
+        // older keywords don't depend on the version
+        let text = "var x div y";
+        for version in [LanguageVersion::Gms22, LanguageVersion::Gms23] {
+            let tokens = lex_version(text, version);
+            assert_eq!(
+                lexemes(text, &tokens),
+                [
+                    (TokenKind::Var, "var"),
+                    (TokenKind::Identifier, "x"),
+                    (TokenKind::IntegerDivide, "div"),
+                    (TokenKind::Identifier, "y"),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn begin_and_end_are_deprecated_from_gms23() {
+        let text = "if (x) begin y = 1; end";
+        let begin = text.find("begin").unwrap();
+        let end = text.find("end").unwrap();
+
+        let tokens = lex_version(text, LanguageVersion::Gms22);
+        assert_eq!(messages(&tokens), []);
+
+        let tokens = lex_version(text, LanguageVersion::Gms23);
+        assert_eq!(
+            messages(&tokens),
+            [
+                (
+                    "'begin' is deprecated, use '{' instead",
+                    range(begin, begin + 5)
+                ),
+                ("'end' is deprecated, use '}' instead", range(end, end + 3)),
+            ]
+        );
+        assert!(
+            tokens
+                .diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.severity == Severity::Warning)
+        );
+
+        // both versions still lex them as braces
+        for version in [LanguageVersion::Gms22, LanguageVersion::Gms23] {
+            let kinds = kinds(&lex_version(text, version));
+            assert!(kinds.contains(&TokenKind::LeftBrace));
+            assert!(kinds.contains(&TokenKind::RightBrace));
+        }
     }
 }
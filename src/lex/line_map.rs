@@ -0,0 +1,188 @@
+use crate::lex::{LineEnding, LineIndex, TokenizedText};
+use crate::source_text::{SourceText, TextSize};
+
+/// A zero-based line and column. Columns are counted in bytes, in characters for
+/// terminals, and in UTF-16 code units for editors and LSP clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinePosition {
+    pub line: u32,
+    pub byte_column: u32,
+    pub char_column: u32,
+    pub utf16_column: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    Byte,
+    Char,
+    Utf16,
+}
+
+/// Converts between byte offsets and line/column positions, using the line table of a
+/// lexed file.
+pub struct LineMap<'a> {
+    text: &'a SourceText<'a>,
+    tokens: &'a TokenizedText,
+}
+
+impl<'a> LineMap<'a> {
+    pub fn new(text: &'a SourceText<'a>, tokens: &'a TokenizedText) -> Self {
+        Self { text, tokens }
+    }
+
+    pub fn line_count(&self) -> usize {
+        let lines = self.tokens.lines.len();
+        if self.tokens.last_line_is_inserted {
+            lines - 1
+        } else {
+            lines
+        }
+    }
+
+    /// The position of `offset`. An offset inside a multi-byte character has the position
+    /// of that character.
+    pub fn position(&self, offset: TextSize) -> LinePosition {
+        debug_assert!(offset <= self.text.len());
+        let line_index = self.tokens.find_line_index(offset);
+        let line_start = self.tokens.lines.get(line_index).start();
+        let mut char_start = offset;
+        while char_start > line_start
+            && char_start < self.text.len()
+            && is_continuation_byte(self.text.get_byte(char_start))
+        {
+            char_start = char_start - 1;
+        }
+        let prefix = self.text.get_slice(line_start..char_start);
+
+        let mut char_column = 0;
+        let mut utf16_column = 0;
+        for &byte in prefix {
+            if !is_continuation_byte(byte) {
+                char_column += 1;
+                utf16_column += utf16_len(byte);
+            }
+        }
+
+        LinePosition {
+            line: line_index.value(),
+            byte_column: prefix.len() as u32,
+            char_column,
+            utf16_column,
+        }
+    }
+
+    /// The offset of a zero-based line and column. Returns `None` if the line doesn't
+    /// exist, if the column is past the end of the line's text, before its line ending,
+    /// or if it falls inside a character.
+    pub fn offset(&self, line: u32, column: u32, unit: ColumnUnit) -> Option<TextSize> {
+        if line as usize >= self.line_count() {
+            return None;
+        }
+
+        let line_index = LineIndex::from(line as usize);
+        let line_start = self.tokens.lines.get(line_index).start();
+        let line_end = self.line_end(line_index);
+        let line_text = self.text.get_slice(line_start..line_end);
+
+        let mut units = 0;
+        for (index, &byte) in line_text.iter().enumerate() {
+            let is_char_start = !is_continuation_byte(byte);
+            if units == column as usize {
+                if is_char_start {
+                    return Some(line_start + index);
+                } else if unit == ColumnUnit::Byte {
+                    return None;
+                }
+            }
+            units += match unit {
+                ColumnUnit::Byte => 1,
+                ColumnUnit::Char if is_char_start => 1,
+                ColumnUnit::Utf16 if is_char_start => utf16_len(byte) as usize,
+                _ => 0,
+            };
+            if units > column as usize {
+                return None;
+            }
+        }
+
+        (units == column as usize).then_some(line_end)
+    }
+
+    // the end of a line's text, before its line ending
+    fn line_end(&self, line_index: LineIndex) -> TextSize {
+        if usize::from(line_index) + 1 == self.tokens.lines.len() {
+            return self.text.len();
+        }
+
+        let next_start = self.tokens.lines.get(line_index + 1).start();
+        let ending_len = match self.tokens.lines.get(line_index).ending() {
+            Some(LineEnding::CrLf) => 2,
+            // the CR of LF+CR starts the next line when it isn't a line ending
+            Some(LineEnding::LfCr) if self.text.get_byte(next_start - 1) == b'\r' => 2,
+            Some(_) => 1,
+            None => 0,
+        };
+        next_start - ending_len
+    }
+}
+
+const fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+// the number of UTF-16 code units in the character that starts with `byte`
+const fn utf16_len(byte: u8) -> u32 {
+    if byte >= 0xF0 { 2 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::lex;
+
+    fn position(line: u32, byte: u32, char: u32, utf16: u32) -> LinePosition {
+        LinePosition {
+            line,
+            byte_column: byte,
+            char_column: char,
+            utf16_column: utf16,
+        }
+    }
+
+    #[test]
+    fn position_counts_characters() {
+        let text = SourceText::from_str("é = 1;\nx = \"😀\";\n");
+        let tokens = lex(&text);
+        let map = LineMap::new(&text, &tokens);
+
+        assert_eq!(map.position(0.into()), position(0, 0, 0, 0));
+        // inside `é`
+        assert_eq!(map.position(1.into()), position(0, 0, 0, 0));
+        assert_eq!(map.position(2.into()), position(0, 2, 1, 1));
+        // the second line starts at 8, and the emoji at 13
+        assert_eq!(map.position(15.into()), position(1, 5, 5, 5));
+        assert_eq!(map.position(17.into()), position(1, 9, 6, 7));
+    }
+
+    #[test]
+    fn offset_stops_at_the_line_ending() {
+        let text = SourceText::from_str("é = 1;\r\nx = \"😀\";\n");
+        let tokens = lex(&text);
+        let map = LineMap::new(&text, &tokens);
+
+        assert_eq!(map.offset(0, 7, ColumnUnit::Byte), Some(7.into()));
+        assert_eq!(map.offset(0, 8, ColumnUnit::Byte), None);
+        assert_eq!(map.offset(0, 6, ColumnUnit::Char), Some(7.into()));
+        assert_eq!(map.offset(0, 7, ColumnUnit::Char), None);
+        assert_eq!(map.offset(0, 1, ColumnUnit::Byte), None);
+
+        assert_eq!(map.offset(1, 5, ColumnUnit::Char), Some(14.into()));
+        assert_eq!(map.offset(1, 6, ColumnUnit::Char), Some(18.into()));
+        assert_eq!(map.offset(1, 6, ColumnUnit::Utf16), None);
+        assert_eq!(map.offset(1, 7, ColumnUnit::Utf16), Some(18.into()));
+        assert_eq!(map.offset(1, 9, ColumnUnit::Utf16), Some(20.into()));
+        assert_eq!(map.offset(1, 10, ColumnUnit::Utf16), None);
+        assert_eq!(map.offset(2, 0, ColumnUnit::Byte), Some(21.into()));
+        assert_eq!(map.offset(3, 0, ColumnUnit::Byte), None);
+    }
+}
@@ -1,6 +1,15 @@
+use crate::lex::identifier_lexer::is_identifier_byte;
 use crate::lex::token::TokenKind;
+use crate::parse::ParseDiagnostic;
+use crate::source_text::TextSize;
+use crate::user_symbols::NumberValue;
 
 pub fn scan_number_or_dot(text: &[u8]) -> (usize, TokenKind) {
+    // a real literal needs a digit after a leading dot, so `._x` is a dot and a name
+    if text[0] == b'.' && !text.get(1).is_some_and(u8::is_ascii_digit) {
+        return (1, TokenKind::Dot);
+    }
+
     let mut index = 0;
     let mut found_dot = false;
 
@@ -25,7 +34,7 @@ pub fn scan_number_or_dot(text: &[u8]) -> (usize, TokenKind) {
     }
 
     let kind = if found_dot {
-        if index == 1  {
+        if index == 1 {
             TokenKind::Dot
         } else {
             TokenKind::RealLiteral
@@ -36,3 +45,302 @@ pub fn scan_number_or_dot(text: &[u8]) -> (usize, TokenKind) {
 
     (index, kind)
 }
+
+/// Scans a hexadecimal literal written as `$FF` or `0xFF`, where `prefix_len` is the
+/// length of the prefix. The token keeps its prefix so that the original spelling can
+/// be recovered from the source text.
+pub fn scan_hex_literal(text: &[u8], prefix_len: usize) -> (usize, TokenKind) {
+    debug_assert!(text.len() >= prefix_len);
+    scan_radix_literal(
+        text,
+        prefix_len,
+        |c| c.is_ascii_hexdigit(),
+        TokenKind::HexIntegerLiteral,
+    )
+}
+
+/// Scans a binary literal written as `0b1010`.
+pub fn scan_binary_literal(text: &[u8]) -> (usize, TokenKind) {
+    debug_assert!(text.starts_with(b"0b"));
+    scan_radix_literal(
+        text,
+        2,
+        |c| matches!(c, b'0' | b'1'),
+        TokenKind::BinaryLiteral,
+    )
+}
+
+// Consume every identifier byte following the prefix so that a malformed literal
+// like `0xFG` or `0b102` is reported as a single error token.
+fn scan_radix_literal(
+    text: &[u8],
+    prefix_len: usize,
+    is_digit: impl Fn(u8) -> bool,
+    kind: TokenKind,
+) -> (usize, TokenKind) {
+    let mut index = prefix_len;
+    let mut has_digits = false;
+    let mut is_valid = true;
+
+    while index < text.len() {
+        let c = text[index];
+
+        if is_digit(c) {
+            has_digits = true;
+        } else if c != b'_' {
+            if !is_identifier_byte(c) {
+                break;
+            }
+            is_valid = false;
+        }
+
+        index += 1;
+    }
+
+    if is_valid && has_digits {
+        (index, kind)
+    } else {
+        (index, TokenKind::Error)
+    }
+}
+
+/// Parses the value of a number literal token that starts at `start`. A literal that
+/// overflows or can't be parsed is reported as an error, and one that can only be stored
+/// with less precision than it was written with is reported as a warning.
+pub fn parse_number_literal(
+    kind: TokenKind,
+    text: &[u8],
+    start: TextSize,
+) -> (NumberValue, Option<ParseDiagnostic>) {
+    let end = start + text.len();
+    let (value, problem) = match kind {
+        TokenKind::IntegerLiteral => parse_integer_literal(text),
+        TokenKind::RealLiteral => parse_real_literal(text),
+        TokenKind::HexIntegerLiteral if text[0] == b'#' => parse_color_literal(text),
+        TokenKind::HexIntegerLiteral => {
+            let prefix_len = if text[0] == b'$' { 1 } else { 2 };
+            parse_radix_literal(&text[prefix_len..], 16)
+        }
+        TokenKind::BinaryLiteral => parse_radix_literal(&text[2..], 2),
+        _ => panic!("expected a number literal, found {:?}", kind),
+    };
+
+    let diagnostic = problem.map(|problem| match problem {
+        NumberProblem::Error(message) => ParseDiagnostic::new(message, start, end),
+        NumberProblem::LosesPrecision(message) => ParseDiagnostic::warning(message, start, end),
+    });
+    (value, diagnostic)
+}
+
+enum NumberProblem {
+    Error(&'static str),
+    // the literal is still valid, but its value isn't exactly what was written
+    LosesPrecision(&'static str),
+}
+
+fn digits_without_separators(text: &[u8]) -> String {
+    text.iter()
+        .filter(|&&c| c != b'_')
+        .map(|&c| c as char)
+        .collect()
+}
+
+fn parse_integer_literal(text: &[u8]) -> (NumberValue, Option<NumberProblem>) {
+    let digits = digits_without_separators(text);
+    match digits.parse::<i64>() {
+        Ok(value) => (NumberValue::Integer(value), None),
+        Err(_) => {
+            // too large for an integer, so it becomes a real like it does at runtime
+            let value = digits.parse::<f64>().unwrap_or(f64::INFINITY);
+            (
+                NumberValue::Real(value),
+                Some(NumberProblem::LosesPrecision(
+                    "integer literal is too large for a 64-bit integer and loses precision",
+                )),
+            )
+        }
+    }
+}
+
+fn parse_real_literal(text: &[u8]) -> (NumberValue, Option<NumberProblem>) {
+    let digits = digits_without_separators(text);
+    let Ok(value) = digits.parse::<f64>() else {
+        return (
+            NumberValue::Real(0.0),
+            Some(NumberProblem::Error("malformed real literal")),
+        );
+    };
+
+    if value.is_infinite() {
+        return (
+            NumberValue::Real(value),
+            Some(NumberProblem::Error("real literal is too large")),
+        );
+    }
+
+    // a double can only distinguish 17 significant digits
+    let significant = digits.trim_matches(|c| c == '0' || c == '.');
+    let significant_digits = significant.bytes().filter(u8::is_ascii_digit).count();
+    if significant_digits > 17 {
+        return (
+            NumberValue::Real(value),
+            Some(NumberProblem::LosesPrecision(
+                "real literal has more precision than a double can represent",
+            )),
+        );
+    }
+
+    (NumberValue::Real(value), None)
+}
+
+// Hexadecimal and binary literals may use all 64 bits, so `$FFFFFFFFFFFFFFFF` is -1.
+fn parse_radix_literal(digits: &[u8], radix: u32) -> (NumberValue, Option<NumberProblem>) {
+    let mut value: u64 = 0;
+    let mut overflowed = false;
+
+    for &c in digits {
+        let Some(digit) = (c as char).to_digit(radix) else {
+            continue;
+        };
+        match value.checked_mul(radix as u64) {
+            Some(shifted) => value = shifted | digit as u64,
+            None => {
+                value = value.wrapping_mul(radix as u64) | digit as u64;
+                overflowed = true;
+            }
+        }
+    }
+
+    let problem = overflowed.then_some(NumberProblem::Error(
+        "integer literal does not fit in 64 bits",
+    ));
+    (NumberValue::Integer(value as i64), problem)
+}
+
+// `#RRGGBB` is stored the same way as `$BBGGRR`
+fn parse_color_literal(text: &[u8]) -> (NumberValue, Option<NumberProblem>) {
+    debug_assert!(text.len() == 7);
+    let (NumberValue::Integer(rgb), _) = parse_radix_literal(&text[1..], 16) else {
+        unreachable!();
+    };
+    let bgr = ((rgb & 0xFF) << 16) | (rgb & 0xFF00) | ((rgb >> 16) & 0xFF);
+    (NumberValue::Integer(bgr), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Severity;
+
+    fn value(kind: TokenKind, text: &str) -> NumberValue {
+        let (value, diagnostic) = parse_number_literal(kind, text.as_bytes(), 0.into());
+        assert_eq!(diagnostic, None, "{text}");
+        value
+    }
+
+    fn problem(kind: TokenKind, text: &str) -> (Severity, &'static str) {
+        let (_, diagnostic) = parse_number_literal(kind, text.as_bytes(), 0.into());
+        let diagnostic = diagnostic.expect(text);
+        assert_eq!(usize::from(diagnostic.range.end()), text.len());
+        (diagnostic.severity, diagnostic.message)
+    }
+
+    #[test]
+    fn hex_literals() {
+        assert_eq!(
+            scan_hex_literal(b"$FF_00;", 1),
+            (6, TokenKind::HexIntegerLiteral)
+        );
+        assert_eq!(
+            scan_hex_literal(b"0xFF)", 2),
+            (4, TokenKind::HexIntegerLiteral)
+        );
+        assert_eq!(
+            value(TokenKind::HexIntegerLiteral, "$FF_00"),
+            NumberValue::Integer(0xFF00)
+        );
+        assert_eq!(
+            value(TokenKind::HexIntegerLiteral, "0xFF"),
+            NumberValue::Integer(0xFF)
+        );
+    }
+
+    // the whole identifier is one error token, rather than `0xF` followed by `G`
+    #[test]
+    fn malformed_hex_literals() {
+        assert_eq!(scan_hex_literal(b"0xFG;", 2), (4, TokenKind::Error));
+        assert_eq!(scan_hex_literal(b"$_;", 1), (2, TokenKind::Error));
+        assert_eq!(scan_hex_literal(b"0x;", 2), (2, TokenKind::Error));
+    }
+
+    #[test]
+    fn number_values() {
+        use NumberValue::{Integer, Real};
+        let cases = [
+            (TokenKind::IntegerLiteral, "1_000", Integer(1000)),
+            (TokenKind::IntegerLiteral, "007", Integer(7)),
+            (TokenKind::RealLiteral, "0.5", Real(0.5)),
+            (TokenKind::RealLiteral, ".25", Real(0.25)),
+            (TokenKind::RealLiteral, "1.", Real(1.0)),
+            (
+                TokenKind::HexIntegerLiteral,
+                "$FFFFFFFFFFFFFFFF",
+                Integer(-1),
+            ),
+            (
+                TokenKind::BinaryLiteral,
+                "0b1010_0001",
+                Integer(0b1010_0001),
+            ),
+            // stored as $BBGGRR
+            (TokenKind::HexIntegerLiteral, "#FF8000", Integer(0x0080FF)),
+        ];
+        for (kind, text, expected) in cases {
+            assert_eq!(value(kind, text), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn number_overflow() {
+        let cases = [
+            (TokenKind::HexIntegerLiteral, "$1_0000_0000_0000_0000"),
+            (TokenKind::BinaryLiteral, &format!("0b1{}", "0".repeat(64))),
+            (TokenKind::RealLiteral, &format!("1{}.0", "0".repeat(400))),
+        ];
+        for (kind, text) in cases {
+            assert_eq!(problem(kind, text).0, Severity::Error, "{text}");
+        }
+    }
+
+    // a literal that loses precision still has a value, so it's only a warning
+    #[test]
+    fn number_precision_loss() {
+        let text = "9223372036854775808";
+        assert_eq!(
+            problem(TokenKind::IntegerLiteral, text),
+            (
+                Severity::Warning,
+                "integer literal is too large for a 64-bit integer and loses precision"
+            )
+        );
+        assert_eq!(
+            parse_number_literal(TokenKind::IntegerLiteral, text.as_bytes(), 0.into()).0,
+            NumberValue::Real(9223372036854775808.0)
+        );
+        assert_eq!(
+            problem(TokenKind::RealLiteral, "0.123456789012345678"),
+            (
+                Severity::Warning,
+                "real literal has more precision than a double can represent"
+            )
+        );
+    }
+
+    #[test]
+    fn leading_dot_needs_a_digit() {
+        assert_eq!(scan_number_or_dot(b".5"), (2, TokenKind::RealLiteral));
+        assert_eq!(scan_number_or_dot(b"._x"), (1, TokenKind::Dot));
+        assert_eq!(scan_number_or_dot(b".."), (1, TokenKind::Dot));
+        assert_eq!(scan_number_or_dot(b"1._"), (3, TokenKind::RealLiteral));
+    }
+}
@@ -1,4 +1,8 @@
 use crate::lex::TokenKind;
+use crate::lex::identifier_lexer::decode_utf8_char;
+use crate::parse::ParseDiagnostic;
+use crate::simd::find_string_stop;
+use crate::source_text::TextSize;
 
 pub fn scan_string_literal(text: &[u8]) -> (usize, TokenKind) {
     debug_assert!(text[0] == b'"');
@@ -6,6 +10,11 @@ pub fn scan_string_literal(text: &[u8]) -> (usize, TokenKind) {
     let mut unterminated = true;
 
     while index < text.len() {
+        index += find_string_stop(&text[index..]);
+        if index >= text.len() {
+            break;
+        }
+
         if text[index] == b'\\' {
             index += 2;
             continue;
@@ -14,14 +23,9 @@ pub fn scan_string_literal(text: &[u8]) -> (usize, TokenKind) {
         if text[index] == b'"' {
             index += 1;
             unterminated = false;
-            break;
         }
 
-        if text[index] == b'\n' {
-            break;
-        }
-
-        index += 1;
+        break;
     }
 
     let kind = if unterminated {
@@ -30,22 +34,46 @@ pub fn scan_string_literal(text: &[u8]) -> (usize, TokenKind) {
         TokenKind::StringLiteral
     };
 
-    (index, kind)
+    (index.min(text.len()), kind)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TemplateStop {
+    Interpolation,
+    Quote,
+    Unterminated,
+}
+
+/// Scans the text of a template string, starting after the `$"` or `}` that opened the
+/// segment, up to and including the `{` of the next interpolation or the closing `"`.
+/// Like regular strings, template strings cannot span multiple lines.
+pub fn scan_template_segment(text: &[u8], prefix_len: usize) -> (usize, TemplateStop) {
+    let mut index = prefix_len;
+
+    while index < text.len() {
+        match text[index] {
+            b'\\' => index += 2,
+            b'{' => return (index + 1, TemplateStop::Interpolation),
+            b'"' => return (index + 1, TemplateStop::Quote),
+            b'\n' => break,
+            _ => index += 1,
+        }
+    }
+
+    (index.min(text.len()), TemplateStop::Unterminated)
 }
 
 pub fn scan_verbatim_string_literal(text: &[u8]) -> (usize, TokenKind) {
     debug_assert!(text[0] == b'@');
-    debug_assert!(text.len() > 2);
+    debug_assert!(text.len() >= 2);
     debug_assert!(text[1] == b'"' || text[1] == b'\'');
-    let mut index = 1;
+    let quote = text[1];
+    let mut index = 2;
     let mut unterminated = true;
 
     while index < text.len() {
-        if text[index] == b'"' {
+        if text[index] == quote {
             index += 1;
-            if text[index] == b'"' {
-                continue;
-            }
             unterminated = false;
             break;
         }
@@ -61,3 +89,170 @@ pub fn scan_verbatim_string_literal(text: &[u8]) -> (usize, TokenKind) {
 
     (index, kind)
 }
+
+/// Decodes the runtime value of a string literal, where `text` includes the quotes and
+/// `start` is the position of the opening quote. Invalid escape sequences are reported
+/// and kept as they were written.
+pub fn decode_string_literal(
+    text: &[u8],
+    start: TextSize,
+    diagnostics: &mut Vec<ParseDiagnostic>,
+) -> String {
+    debug_assert!(text.len() >= 2 && text[0] == b'"' && text[text.len() - 1] == b'"');
+    let content = &text[1..text.len() - 1];
+    let content_start = start + 1;
+    let mut value = String::with_capacity(content.len());
+    let mut run_start = 0;
+    let mut index = 0;
+
+    while index < content.len() {
+        if content[index] != b'\\' {
+            index += 1;
+            continue;
+        }
+
+        value.push_str(&String::from_utf8_lossy(&content[run_start..index]));
+
+        let escape_start = index;
+        let (len, result) = decode_escape(&content[index + 1..]);
+        index += 1 + len;
+        run_start = index;
+
+        match result {
+            Ok(Some(c)) => value.push(c),
+            Ok(None) => {}
+            Err(message) => {
+                diagnostics.push(ParseDiagnostic::new(
+                    message,
+                    content_start + escape_start,
+                    content_start + index,
+                ));
+                value.push_str(&String::from_utf8_lossy(&content[escape_start..index]));
+            }
+        }
+    }
+
+    value.push_str(&String::from_utf8_lossy(&content[run_start..]));
+    value
+}
+
+/// Decodes the runtime value of a verbatim string literal, where `text` includes the `@`
+/// and the quotes. Verbatim strings have no escape sequences and end at the first quote
+/// that matches the opening one, so the value is everything between the quotes.
+pub fn decode_verbatim_string_literal(text: &[u8]) -> String {
+    debug_assert!(text.len() >= 3 && text[0] == b'@');
+    String::from_utf8_lossy(&text[2..text.len() - 1]).into_owned()
+}
+
+// Decodes the escape sequence that follows a backslash, returning the number of bytes
+// consumed. `Ok(None)` is a line continuation, which has no value.
+fn decode_escape(text: &[u8]) -> (usize, Result<Option<char>, &'static str>) {
+    let Some(&c) = text.first() else {
+        return (0, Err("invalid escape sequence"));
+    };
+
+    let value = match c {
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'b' => '\u{8}',
+        b'f' => '\u{c}',
+        b'v' => '\u{b}',
+        b'a' => '\u{7}',
+        b'\\' => '\\',
+        b'"' => '"',
+        b'\'' => '\'',
+        b'\n' => return (1, Ok(None)),
+        b'\r' if text.get(1) == Some(&b'\n') => return (2, Ok(None)),
+        b'x' => return decode_hex_escape(text),
+        b'u' => return decode_unicode_escape(text),
+        b'0'..=b'7' => return decode_octal_escape(text),
+        _ => {
+            let len = match decode_utf8_char(text) {
+                Ok((_, len)) => len,
+                Err(len) => len,
+            };
+            return (len, Err("invalid escape sequence"));
+        }
+    };
+
+    (1, Ok(Some(value)))
+}
+
+// `\xHH`
+fn decode_hex_escape(text: &[u8]) -> (usize, Result<Option<char>, &'static str>) {
+    debug_assert!(text[0] == b'x');
+    let (digits, value) = scan_hex_digits(&text[1..], 2);
+    if digits != 2 {
+        return (1 + digits, Err("expected two hexadecimal digits"));
+    }
+    (1 + digits, Ok(char::from_u32(value)))
+}
+
+// `\uXXXX`, a surrogate pair of `\uXXXX\uXXXX`, or `\u{X}` with up to six digits
+fn decode_unicode_escape(text: &[u8]) -> (usize, Result<Option<char>, &'static str>) {
+    debug_assert!(text[0] == b'u');
+
+    if text.get(1) == Some(&b'{') {
+        let (digits, value) = scan_hex_digits(&text[2..], 6);
+        let len = 2 + digits;
+        if digits == 0 || text.get(len) != Some(&b'}') {
+            return (len, Err("invalid unicode escape sequence"));
+        }
+        return match char::from_u32(value) {
+            Some(c) => (len + 1, Ok(Some(c))),
+            None => (len + 1, Err("invalid unicode code point")),
+        };
+    }
+
+    let (digits, high) = scan_hex_digits(&text[1..], 4);
+    if digits != 4 {
+        return (1 + digits, Err("expected four hexadecimal digits"));
+    }
+
+    if (0xD800..0xDC00).contains(&high) && text[5..].starts_with(b"\\u") {
+        let (digits, low) = scan_hex_digits(&text[7..], 4);
+        if digits == 4 && (0xDC00..0xE000).contains(&low) {
+            let value = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+            return (11, Ok(char::from_u32(value)));
+        }
+    }
+
+    match char::from_u32(high) {
+        Some(c) => (5, Ok(Some(c))),
+        None => (5, Err("invalid unicode code point")),
+    }
+}
+
+// `\ooo` with up to three digits, as long as the value fits in a byte
+fn decode_octal_escape(text: &[u8]) -> (usize, Result<Option<char>, &'static str>) {
+    let mut value = 0;
+    let mut len = 0;
+
+    while len < 3 && len < text.len() && matches!(text[len], b'0'..=b'7') {
+        let next = value * 8 + (text[len] - b'0') as u32;
+        if next > 0xFF {
+            break;
+        }
+        value = next;
+        len += 1;
+    }
+
+    debug_assert!(len > 0);
+    (len, Ok(char::from_u32(value)))
+}
+
+fn scan_hex_digits(text: &[u8], max_digits: usize) -> (usize, u32) {
+    let mut value = 0;
+    let mut len = 0;
+
+    while len < max_digits && len < text.len() {
+        let Some(digit) = (text[len] as char).to_digit(16) else {
+            break;
+        };
+        value = value * 16 + digit;
+        len += 1;
+    }
+
+    (len, value)
+}
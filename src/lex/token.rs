@@ -65,6 +65,40 @@ impl Debug for Token {
     }
 }
 
+/// The quote character of a verbatim string literal, stored in the token's payload.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    Double,
+    Single,
+}
+
+impl QuoteStyle {
+    pub fn from_byte(quote: u8) -> Self {
+        debug_assert!(quote == b'"' || quote == b'\'');
+        if quote == b'\'' {
+            QuoteStyle::Single
+        } else {
+            QuoteStyle::Double
+        }
+    }
+
+    pub fn from_payload(payload: u32) -> Self {
+        if payload == QuoteStyle::Single as u32 {
+            QuoteStyle::Single
+        } else {
+            QuoteStyle::Double
+        }
+    }
+
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            QuoteStyle::Double => b'"',
+            QuoteStyle::Single => b'\'',
+        }
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenKind {
@@ -73,6 +107,7 @@ pub enum TokenKind {
     FileEnd,
     SingleLineComment,
     MultiLineComment,
+    DocComment,
     ListAccessor,
     MapAccessor,
     GridAccessor,
@@ -133,6 +168,8 @@ pub enum TokenKind {
     Identifier,
     BooleanLiteral,
     IntegerLiteral,
+    HexIntegerLiteral,
+    BinaryLiteral,
     RealLiteral,
     StringLiteral,
     VerbatimStringLiteral,
@@ -185,7 +222,7 @@ impl TokenKind {
     pub fn is_comment(&self) -> bool {
         matches!(
             self,
-            TokenKind::SingleLineComment | TokenKind::MultiLineComment
+            TokenKind::SingleLineComment | TokenKind::MultiLineComment | TokenKind::DocComment
         )
     }
 
@@ -194,6 +231,7 @@ impl TokenKind {
             self,
             TokenKind::SingleLineComment
                 | TokenKind::MultiLineComment
+                | TokenKind::DocComment
                 | TokenKind::Whitespace
                 | TokenKind::LineBreak
         )
@@ -257,9 +295,12 @@ impl TokenKind {
             self,
             TokenKind::BooleanLiteral
                 | TokenKind::IntegerLiteral
+                | TokenKind::HexIntegerLiteral
+                | TokenKind::BinaryLiteral
                 | TokenKind::RealLiteral
                 | TokenKind::StringLiteral
                 | TokenKind::VerbatimStringLiteral
+                | TokenKind::SimpleTemplateString
         )
     }
 
@@ -1,35 +1,525 @@
 use crate::chunked_index_vec::ChunkedIndexVec;
-use crate::lex::TokenKind;
-use crate::lex::token::{Token, TokenIndex};
+use crate::lex::doc_comment::{DocBlock, parse_doc_block};
+use crate::lex::feather::{RuleId, Suppression};
+use crate::lex::lexer::{
+    DelimiterError, DelimiterSnapshot, is_close_delimiter, is_open_delimiter, relex,
+};
+use crate::lex::string_lexer::{
+    decode_string_literal, decode_verbatim_string_literal, scan_string_literal,
+    scan_verbatim_string_literal,
+};
+use crate::lex::token::{QuoteStyle, Token, TokenIndex};
+use crate::lex::{LexOptions, TokenKind};
 use crate::parse::ParseDiagnostic;
-use crate::source_text::TextSize;
+use crate::source_text::{SourceText, TextEdit, TextRange, TextSize};
 use crate::typed_index;
+use crate::user_symbols::{IdentifierId, NumberLiteralId, StringLiteralId, UserSymbols};
+use std::ops::Range;
+
+// the payload of a `StringLiteral` token once its escape errors have been reported
+const ESCAPES_REPORTED: u32 = 1;
+
+// mirrors `Lexer::id_to_payload`
+fn payload_to_id<I: From<usize>>(payload: u32) -> Option<I> {
+    payload.checked_sub(1).map(|id| I::from(id as usize))
+}
 
 pub struct TokenizedText {
     pub(crate) tokens: ChunkedIndexVec<Token, TokenIndex>,
+    trivia: ChunkedIndexVec<Trivia, TriviaIndex>,
+    // the index of the first trivia piece after each token
+    token_trivia: ChunkedIndexVec<TriviaIndex, TokenIndex>,
     comments: ChunkedIndexVec<Comment, CommentIndex>,
     pub(crate) lines: ChunkedIndexVec<Line, LineIndex>,
     pub diagnostics: Vec<ParseDiagnostic>,
     pub last_line_is_inserted: bool,
+    /// The line ending to use when writing the file back. This is the most common ending
+    /// in the file, or its normalized form if the file was lexed with
+    /// `LineEndingPolicy::Normalize`.
+    pub line_ending: LineEnding,
+    pub has_mixed_line_endings: bool,
+    // how many times each line ending is found in the file, indexed by `LineEnding`
+    pub(crate) line_ending_counts: [usize; 4],
+    /// Whether any delimiter is mismatched, closes nothing, or is never closed. Each one is
+    /// also reported in `diagnostics`.
+    pub has_mismatched_brackets: bool,
+    // kept so that an edit only has to find the delimiter errors around it again
+    pub(crate) delimiter_errors: Vec<DelimiterError>,
+    pub(crate) delimiter_snapshots: Vec<DelimiterSnapshot>,
+    pub(crate) suppressions: Vec<Suppression>,
+    // kept so that edits are lexed the same way as the whole file
+    pub(crate) options: LexOptions,
+}
+
+impl Default for TokenizedText {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TokenizedText {
     pub fn new() -> TokenizedText {
         TokenizedText {
             tokens: ChunkedIndexVec::new(),
+            trivia: ChunkedIndexVec::new(),
+            token_trivia: ChunkedIndexVec::new(),
             comments: ChunkedIndexVec::new(),
             lines: ChunkedIndexVec::new(),
             diagnostics: Vec::new(),
             last_line_is_inserted: false,
+            line_ending: LineEnding::Lf,
+            has_mixed_line_endings: false,
+            line_ending_counts: [0; 4],
+            has_mismatched_brackets: false,
+            delimiter_errors: Vec::new(),
+            delimiter_snapshots: Vec::new(),
+            suppressions: Vec::new(),
+            options: LexOptions::default(),
         }
     }
 
+    /// Updates the tokens after `edit` was made to the text, given the edited `text`. Only
+    /// the lines around the edit are lexed again, until the tokens line up with the old
+    /// ones; the rest are shifted to their new positions. The result is the same as
+    /// lexing the edited text from scratch.
+    pub fn apply_edit(&mut self, text: &SourceText, edit: TextEdit) {
+        let tokens = std::mem::take(self);
+        *self = relex(tokens, text, edit, None);
+    }
+
+    /// Like [`TokenizedText::apply_edit`], for tokens that were lexed with symbols.
+    pub fn apply_edit_with_symbols<'src>(
+        &mut self,
+        text: &'src SourceText,
+        edit: TextEdit,
+        symbols: &mut UserSymbols<'src>,
+    ) {
+        let tokens = std::mem::take(self);
+        *self = relex(tokens, text, edit, Some(symbols));
+    }
+
     pub fn add_token(&mut self, token: Token) -> TokenIndex {
+        self.token_trivia.push(TriviaIndex::from(self.trivia.len()));
         self.tokens.push(token)
     }
 
-    pub fn add_comment(&mut self, comment: Comment) -> CommentIndex {
-        self.comments.push(comment)
+    pub fn add_trivia(&mut self, trivia: Trivia) -> TriviaIndex {
+        debug_assert!(
+            !self.tokens.is_empty(),
+            "trivia must follow the FileStart token"
+        );
+        self.trivia.push(trivia)
+    }
+
+    pub fn trivia_count(&self) -> usize {
+        self.trivia.len()
+    }
+
+    pub fn get_trivia(&self, trivia: TriviaIndex) -> &Trivia {
+        self.trivia.get(trivia)
+    }
+
+    /// The end of a token's text. Tokens don't store their length, so this is the start
+    /// of whatever follows the token.
+    pub fn get_end(&self, token: TokenIndex) -> TextSize {
+        let (first, last) = self.trivia_after(token);
+        if first < last {
+            self.trivia.get(first).start()
+        } else if usize::from(token) + 1 < self.tokens.len() {
+            self.get_start(token + 1)
+        } else {
+            self.get_start(token)
+        }
+    }
+
+    /// The trivia before a token that belongs to it: everything after the first line break
+    /// since the previous token.
+    pub fn leading_trivia(&self, token: TokenIndex) -> impl Iterator<Item = TriviaIndex> {
+        let range = if token.value() == 0 {
+            (TriviaIndex::from(0), TriviaIndex::from(0))
+        } else {
+            let (first, last) = self.trivia_after(token - 1);
+            (self.split_trivia(token - 1, first, last), last)
+        };
+        Self::trivia_range(range)
+    }
+
+    /// The trivia after a token that belongs to it: everything on the rest of its line,
+    /// up to and including the line break.
+    pub fn trailing_trivia(&self, token: TokenIndex) -> impl Iterator<Item = TriviaIndex> {
+        let (first, last) = self.trivia_after(token);
+        Self::trivia_range((first, self.split_trivia(token, first, last)))
+    }
+
+    pub fn comment_count(&self) -> usize {
+        self.comments.len()
+    }
+
+    pub fn get_comment(&self, comment: CommentIndex) -> &Comment {
+        self.comments.get(comment)
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = (CommentIndex, &Comment)> {
+        self.comments.iter()
+    }
+
+    pub fn leading_comments(&self, token: TokenIndex) -> impl Iterator<Item = &Comment> {
+        self.leading_trivia(token)
+            .filter_map(|trivia| self.find_comment(trivia))
+    }
+
+    pub fn trailing_comments(&self, token: TokenIndex) -> impl Iterator<Item = &Comment> {
+        self.trailing_trivia(token)
+            .filter_map(|trivia| self.find_comment(trivia))
+    }
+
+    /// Parses every run of `///` comment lines that ends on the line before a token.
+    /// Runs that are followed by a blank line or by another comment don't document
+    /// anything and are skipped.
+    pub fn doc_blocks(&self, text: &SourceText) -> Vec<DocBlock> {
+        let mut blocks = Vec::new();
+        let mut lines = Vec::new();
+        let mut last: Option<&Comment> = None;
+
+        for (_, comment) in self.comments.iter() {
+            let trivia = self.trivia.get(comment.trivia());
+            let is_doc_line = trivia.kind() == TokenKind::DocComment
+                && comment.kind() == CommentKind::OwnLine
+                && comment.is_leading();
+            if !is_doc_line {
+                continue;
+            }
+
+            if let Some(previous) = last
+                && !self.is_next_doc_line(previous, comment)
+            {
+                blocks.extend(self.finish_doc_block(text, &lines, previous));
+                lines.clear();
+            }
+
+            lines.push(TextRange::new(trivia.start(), trivia.end()));
+            last = Some(comment);
+        }
+
+        if let Some(previous) = last {
+            blocks.extend(self.finish_doc_block(text, &lines, previous));
+        }
+        blocks
+    }
+
+    /// The doc block right before `token`, if there is one.
+    pub fn doc_block(&self, text: &SourceText, token: TokenIndex) -> Option<DocBlock> {
+        let mut lines = Vec::new();
+        let mut last: Option<&Comment> = None;
+
+        for comment in self.leading_comments(token) {
+            let trivia = self.trivia.get(comment.trivia());
+            if trivia.kind() != TokenKind::DocComment {
+                lines.clear();
+                last = None;
+                continue;
+            }
+            if let Some(previous) = last
+                && !self.is_next_doc_line(previous, comment)
+            {
+                lines.clear();
+            }
+            lines.push(TextRange::new(trivia.start(), trivia.end()));
+            last = Some(comment);
+        }
+
+        self.finish_doc_block(text, &lines, last?)
+    }
+
+    /// The line ranges where Feather rules are turned off by `// Feather disable` style
+    /// comments, ordered by their first line.
+    pub fn suppressions(&self) -> &[Suppression] {
+        &self.suppressions
+    }
+
+    /// Whether `rule` is turned off on the line where `token` starts.
+    pub fn is_suppressed(&self, rule: RuleId, token: TokenIndex) -> bool {
+        let line = self.find_line_index(self.get_start(token));
+        self.suppressions
+            .iter()
+            .take_while(|suppression| suppression.start_line <= line)
+            .any(|suppression| suppression.applies_to(rule, line))
+    }
+
+    // the block has to end on the line before its token
+    fn finish_doc_block(
+        &self,
+        text: &SourceText,
+        lines: &[TextRange],
+        last: &Comment,
+    ) -> Option<DocBlock> {
+        let (_, gap_end) = self.trivia_after(last.token() - 1);
+        if !self.is_next_doc_line_end(last.trivia() + 1, gap_end) {
+            return None;
+        }
+        Some(parse_doc_block(text, lines, last.token()))
+    }
+
+    fn is_next_doc_line(&self, previous: &Comment, comment: &Comment) -> bool {
+        previous.token() == comment.token()
+            && self.is_next_doc_line_end(previous.trivia() + 1, comment.trivia())
+    }
+
+    // Whether the trivia in between is a single line break and indentation
+    fn is_next_doc_line_end(&self, first: TriviaIndex, last: TriviaIndex) -> bool {
+        let mut line_breaks = 0;
+        for index in Self::trivia_range((first, last)) {
+            match self.trivia.get(index).kind() {
+                TokenKind::Whitespace => {}
+                TokenKind::LineBreak => line_breaks += 1,
+                _ => return false,
+            }
+        }
+        line_breaks == 1
+    }
+
+    fn find_comment(&self, trivia: TriviaIndex) -> Option<&Comment> {
+        if !self.trivia.get(trivia).kind().is_comment() {
+            return None;
+        }
+
+        let index = self.first_comment_from(trivia);
+        debug_assert!(self.comments.get(index.into()).trivia() == trivia);
+        Some(self.comments.get(index.into()))
+    }
+
+    // the index of the first comment at or after `trivia`
+    fn first_comment_from(&self, trivia: TriviaIndex) -> usize {
+        let mut left = 0;
+        let mut right = self.comments.len();
+        while left < right {
+            let mid = (left + right) / 2;
+            if self.comments.get(mid.into()).trivia() < trivia {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        left
+    }
+
+    pub(crate) fn trivia_after(&self, token: TokenIndex) -> (TriviaIndex, TriviaIndex) {
+        let first = *self.token_trivia.get(token);
+        let last = if usize::from(token) + 1 < self.tokens.len() {
+            *self.token_trivia.get(token + 1)
+        } else {
+            TriviaIndex::from(self.trivia.len())
+        };
+        (first, last)
+    }
+
+    // Where the trivia after `token` stops trailing it and starts leading the next token
+    fn split_trivia(
+        &self,
+        token: TokenIndex,
+        first: TriviaIndex,
+        last: TriviaIndex,
+    ) -> TriviaIndex {
+        if self.get_kind(token) == TokenKind::FileStart {
+            return first;
+        }
+
+        let mut index = first;
+        while index < last {
+            let kind = self.trivia.get(index).kind();
+            index += 1;
+            if kind == TokenKind::LineBreak {
+                break;
+            }
+        }
+        index
+    }
+
+    fn trivia_range(
+        (first, last): (TriviaIndex, TriviaIndex),
+    ) -> impl Iterator<Item = TriviaIndex> {
+        (usize::from(first)..usize::from(last)).map(TriviaIndex::from)
+    }
+
+    /// Classifies the comments in the trivia from `start` to `end` and attaches them to
+    /// tokens, starting with the trivia after `token`. Called once lexing is done.
+    pub(crate) fn attach_comments(
+        &mut self,
+        token: TokenIndex,
+        start: TriviaIndex,
+        end: TriviaIndex,
+    ) {
+        for token_value in usize::from(token)..self.tokens.len() {
+            let token = TokenIndex::from(token_value);
+            let (first, last) = self.trivia_after(token);
+            if first >= end {
+                break;
+            }
+            let split = self.split_trivia(token, first, last);
+            let follows_file_start = self.get_kind(token) == TokenKind::FileStart;
+            let precedes_file_end = usize::from(token) + 2 == self.tokens.len();
+
+            let mut follows_line_break = false;
+            for index in Self::trivia_range((first, last)) {
+                let kind = self.trivia.get(index).kind();
+                if kind == TokenKind::LineBreak {
+                    follows_line_break = true;
+                    continue;
+                }
+                if !kind.is_comment() || index < start || index >= end {
+                    continue;
+                }
+
+                let is_leading = index >= split;
+                let kind = self.classify_comment(
+                    index,
+                    last,
+                    follows_file_start || follows_line_break,
+                    precedes_file_end,
+                );
+                let comment = Comment {
+                    kind,
+                    trivia: index,
+                    token: if is_leading { token + 1 } else { token },
+                    is_leading,
+                };
+                self.comments.push(comment);
+            }
+        }
+    }
+
+    /// Replaces the old tokens in `tokens` and the old trivia in `trivia` with the ones
+    /// that were lexed again after `edit` and added at the end, from `new_tokens` and
+    /// `new_trivia` on. The tokens, trivia and comments after the replaced ones are moved
+    /// in place and shifted to their new positions, and `map_token` gives the new index of
+    /// each token that they refer to. Comments in the new trivia are attached again.
+    pub(crate) fn replace(
+        &mut self,
+        tokens: Range<TokenIndex>,
+        trivia: Range<TriviaIndex>,
+        new_tokens: TokenIndex,
+        new_trivia: TriviaIndex,
+        edit: TextEdit,
+        map_token: impl Fn(TokenIndex) -> TokenIndex,
+    ) {
+        let first_comment = self.first_comment_from(trivia.start);
+        let tail_comment = self.first_comment_from(trivia.end);
+
+        let replacement = self.tokens.split_off(new_tokens.into());
+        let replacement_trivia_index: Vec<TriviaIndex> = self
+            .token_trivia
+            .split_off(new_tokens.into())
+            .into_iter()
+            .map(|index| index - new_trivia + trivia.start)
+            .collect();
+        let replacement_trivia = self.trivia.split_off(new_trivia.into());
+        let tail_trivia = trivia.start + replacement_trivia.len();
+        let shift = |offset| edit.shift(offset);
+
+        let token_range = usize::from(tokens.start)..usize::from(tokens.end);
+        self.tokens
+            .splice(token_range.clone(), &replacement, |token| {
+                let kind = token.kind();
+                let mut payload = token.payload();
+                if (is_open_delimiter(kind) || is_close_delimiter(kind)) && payload != 0 {
+                    payload = map_token(TokenIndex::from(payload as usize)).value();
+                }
+                *token = Token::new(
+                    kind,
+                    token.has_leading_space(),
+                    payload,
+                    shift(token.start()),
+                );
+            });
+        self.token_trivia
+            .splice(token_range, &replacement_trivia_index, |index| {
+                *index = *index - trivia.end + tail_trivia;
+            });
+        self.trivia.splice(
+            usize::from(trivia.start)..usize::from(trivia.end),
+            &replacement_trivia,
+            |piece| {
+                piece.start = shift(piece.start);
+                piece.end = shift(piece.end);
+            },
+        );
+
+        let comment_count = self.comments.len();
+        let comments_token = TokenIndex::from(usize::from(tokens.start).saturating_sub(1));
+        self.attach_comments(comments_token, trivia.start, tail_trivia);
+        let new_comments = self.comments.split_off(comment_count);
+        self.comments
+            .splice(first_comment..tail_comment, &new_comments, |comment| {
+                comment.trivia = comment.trivia - trivia.end + tail_trivia;
+                comment.token = map_token(comment.token);
+            });
+    }
+
+    pub(crate) fn add_line(&mut self, line: Line) {
+        line.count_line_endings(&mut self.line_ending_counts, true);
+        self.lines.push(line);
+    }
+
+    /// Replaces the old lines in `lines` with `new_lines`, which were found again after
+    /// `edit`, and shifts the lines after them to their new positions. Returns the old
+    /// lines.
+    pub(crate) fn replace_lines(
+        &mut self,
+        lines: Range<LineIndex>,
+        new_lines: &[Line],
+        edit: TextEdit,
+    ) -> Vec<Line> {
+        let range = usize::from(lines.start)..usize::from(lines.end);
+        let old_lines: Vec<Line> = range
+            .clone()
+            .map(|index| *self.lines.get(index.into()))
+            .collect();
+
+        for line in &old_lines {
+            line.count_line_endings(&mut self.line_ending_counts, false);
+        }
+        for line in new_lines {
+            line.count_line_endings(&mut self.line_ending_counts, true);
+        }
+        self.lines
+            .splice(range, new_lines, |line| line.start = edit.shift(line.start));
+        old_lines
+    }
+
+    /// The trivia right before `token`.
+    pub(crate) fn trivia_before(&self, token: TokenIndex) -> (TriviaIndex, TriviaIndex) {
+        if token == 0 {
+            (TriviaIndex::from(0), *self.token_trivia.get(token))
+        } else {
+            self.trivia_after(token - 1)
+        }
+    }
+
+    fn classify_comment(
+        &self,
+        comment: TriviaIndex,
+        last: TriviaIndex,
+        starts_line: bool,
+        ends_file: bool,
+    ) -> CommentKind {
+        if starts_line {
+            return CommentKind::OwnLine;
+        }
+
+        for index in Self::trivia_range((comment + 1, last)) {
+            match self.trivia.get(index).kind() {
+                TokenKind::Whitespace => continue,
+                TokenKind::LineBreak => return CommentKind::EndOfLine,
+                _ => return CommentKind::Remaining,
+            }
+        }
+
+        // nothing but whitespace before the next token
+        if ends_file {
+            CommentKind::EndOfLine
+        } else {
+            CommentKind::Remaining
+        }
     }
 
     pub fn token_count(&self) -> usize {
@@ -37,7 +527,7 @@ impl TokenizedText {
     }
 
     pub fn find_line_index(&self, position: TextSize) -> LineIndex {
-        debug_assert!(self.lines.len() > 0);
+        debug_assert!(!self.lines.is_empty());
 
         let mut left = 0;
         let mut right = self.lines.len();
@@ -67,7 +557,7 @@ impl TokenizedText {
     pub fn get_column_number(&self, token: TokenIndex) -> u32 {
         let token_info = self.tokens.get(token);
         let line_info = self.lines.get(self.find_line_index(token_info.start()));
-        (token_info.start() + line_info.start() + 1).value()
+        (token_info.start() - line_info.start() + 1).value()
     }
 
     pub fn get_line_number(&self, token: TokenIndex) -> u32 {
@@ -87,6 +577,110 @@ impl TokenizedText {
         self.tokens.get(token).start()
     }
 
+    /// The delimiter that closes `token` if it opens one, or the one it closes if it
+    /// closes one. Template middles are treated as opening the next segment. Returns
+    /// `None` for other tokens, for `begin` and `end`, and for delimiters that aren't
+    /// matched.
+    pub fn matching_delimiter(&self, token: TokenIndex) -> Option<TokenIndex> {
+        let token_info = self.tokens.get(token);
+        let kind = token_info.kind();
+        let payload = TokenIndex::from(token_info.payload() as usize);
+
+        if is_open_delimiter(kind) {
+            // unmatched template middles keep the segment before them in the payload
+            (payload > token).then_some(payload)
+        } else if is_close_delimiter(kind) && payload != 0 {
+            (self.tokens.get(payload).payload() == token.value()).then_some(payload)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_quote_style(&self, token: TokenIndex) -> QuoteStyle {
+        let token_info = self.tokens.get(token);
+        debug_assert!(token_info.kind() == TokenKind::VerbatimStringLiteral);
+        QuoteStyle::from_payload(token_info.payload())
+    }
+
+    /// The id of an `Identifier` or `MacroName` token's name, if the text was lexed with
+    /// symbols and the id fit in the token.
+    pub fn get_identifier_id(&self, token: TokenIndex) -> Option<IdentifierId> {
+        let token_info = self.tokens.get(token);
+        debug_assert!(matches!(
+            token_info.kind(),
+            TokenKind::Identifier | TokenKind::MacroName
+        ));
+        payload_to_id(token_info.payload())
+    }
+
+    /// The id of a number literal token's value, if the text was lexed with symbols and
+    /// the id fit in the token.
+    pub fn get_number_literal_id(&self, token: TokenIndex) -> Option<NumberLiteralId> {
+        let token_info = self.tokens.get(token);
+        debug_assert!(matches!(
+            token_info.kind(),
+            TokenKind::IntegerLiteral
+                | TokenKind::RealLiteral
+                | TokenKind::HexIntegerLiteral
+                | TokenKind::BinaryLiteral
+        ));
+        payload_to_id(token_info.payload())
+    }
+
+    /// Decodes the runtime value of a `StringLiteral` or `VerbatimStringLiteral` token,
+    /// reporting invalid escape sequences to `diagnostics`.
+    pub fn get_string_value(
+        &self,
+        text: &SourceText,
+        token: TokenIndex,
+        diagnostics: &mut Vec<ParseDiagnostic>,
+    ) -> String {
+        let token_info = self.tokens.get(token);
+        debug_assert!(matches!(
+            token_info.kind(),
+            TokenKind::StringLiteral | TokenKind::VerbatimStringLiteral
+        ));
+        let start = token_info.start();
+        let slice = text.get_slice(start..);
+
+        match token_info.kind() {
+            TokenKind::StringLiteral => {
+                let (len, _) = scan_string_literal(slice);
+                decode_string_literal(&slice[..len], start, diagnostics)
+            }
+            TokenKind::VerbatimStringLiteral => {
+                let (len, kind) = scan_verbatim_string_literal(slice);
+                if kind == TokenKind::Error {
+                    // unterminated, the string runs to the end of the file
+                    return String::from_utf8_lossy(&slice[2..]).into_owned();
+                }
+                decode_verbatim_string_literal(&slice[..len])
+            }
+            _ => String::from_utf8_lossy(text.get_slice(start..self.get_end(token))).into_owned(),
+        }
+    }
+
+    /// Decodes a string literal token and interns its value, so that string constants
+    /// can be compared by id. Invalid escape sequences are added to `diagnostics` the
+    /// first time a token is interned.
+    pub fn intern_string_literal(
+        &mut self,
+        text: &SourceText,
+        token: TokenIndex,
+        symbols: &mut UserSymbols,
+    ) -> StringLiteralId {
+        let mut diagnostics = Vec::new();
+        let value = self.get_string_value(text, token, &mut diagnostics);
+
+        // a `StringLiteral` token's payload is otherwise unused
+        let token_info = self.tokens.get_mut(token);
+        if token_info.kind() == TokenKind::StringLiteral && token_info.payload() == 0 {
+            token_info.set_payload(ESCAPES_REPORTED);
+            self.diagnostics.extend(diagnostics);
+        }
+        symbols.string_literals.push(value.into_boxed_str())
+    }
+
     pub fn has_leading_whitespace(&self, token: TokenIndex) -> bool {
         self.tokens.get(token).has_leading_space()
     }
@@ -117,15 +711,56 @@ impl TokenizedText {
     }
 }
 
-pub struct Comment {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    /// A lone CR, used by classic Mac OS.
+    Cr,
+    /// LF followed by a CR, produced by some older GameMaker exports.
+    LfCr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+            LineEnding::LfCr => "\n\r",
+        }
+    }
+
+    /// The ending to write instead of a legacy one: CR becomes LF, and LF+CR becomes
+    /// CR+LF.
+    pub fn normalized(self) -> LineEnding {
+        match self {
+            LineEnding::Lf | LineEnding::Cr => LineEnding::Lf,
+            LineEnding::CrLf | LineEnding::LfCr => LineEnding::CrLf,
+        }
+    }
+}
+
+/// A piece of text between tokens: whitespace, a line break or a comment. Trivia
+/// pieces cover every byte that isn't part of a token, so the tokens and trivia of a
+/// file can be re-emitted to reproduce it exactly.
+#[derive(Debug, Clone, Copy)]
+pub struct Trivia {
+    kind: TokenKind,
     start: TextSize,
     end: TextSize,
 }
-typed_index!(pub struct CommentIndex(u32));
+typed_index!(pub struct TriviaIndex(u32));
 
-impl Comment {
-    pub fn new(start: TextSize, end: TextSize) -> Self {
-        Comment { start, end }
+impl Trivia {
+    pub fn new(kind: TokenKind, start: TextSize, end: TextSize) -> Self {
+        debug_assert!(kind.is_comment_or_whitespace());
+        debug_assert!(start <= end);
+        Trivia { kind, start, end }
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        self.kind
     }
 
     pub fn start(&self) -> TextSize {
@@ -137,21 +772,73 @@ impl Comment {
     }
 }
 
+/// A comment, classified by its position and attached to a neighbouring token.
+#[derive(Debug, Clone, Copy)]
+pub struct Comment {
+    kind: CommentKind,
+    trivia: TriviaIndex,
+    token: TokenIndex,
+    is_leading: bool,
+}
+typed_index!(pub struct CommentIndex(u32));
+
+impl Comment {
+    pub fn kind(&self) -> CommentKind {
+        self.kind
+    }
+
+    pub fn trivia(&self) -> TriviaIndex {
+        self.trivia
+    }
+
+    /// The token that the comment is attached to.
+    pub fn token(&self) -> TokenIndex {
+        self.token
+    }
+
+    /// Whether the comment is leading trivia of its token, rather than trailing trivia.
+    pub fn is_leading(&self) -> bool {
+        self.is_leading
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommentKind {
+    /// The only thing on its line, apart from whitespace.
     OwnLine,
+    /// Follows a token and ends its line.
     EndOfLine,
+    /// Followed by another token on the same line.
     Remaining,
 }
 
+#[derive(Clone, Copy)]
 pub struct Line {
     start: TextSize,
     indent: u32,
+    // The line ending at the end of the line, and the CRs on the line that don't end it.
+    // They're kept so that the file's line endings can be counted again after an edit.
+    ending: Option<LineEnding>,
+    ignored_crs: u32,
 }
 typed_index!(pub struct LineIndex(u32));
 
 impl Line {
     pub fn new(start: TextSize) -> Self {
-        Line { start, indent: 0 }
+        Self::with_ending(start, None, 0)
+    }
+
+    pub(crate) fn with_ending(
+        start: TextSize,
+        ending: Option<LineEnding>,
+        ignored_crs: u32,
+    ) -> Self {
+        Line {
+            start,
+            indent: 0,
+            ending,
+            ignored_crs,
+        }
     }
 
     pub fn start(&self) -> TextSize {
@@ -165,4 +852,334 @@ impl Line {
     pub fn set_indent(&mut self, indent: u32) {
         self.indent = indent;
     }
+
+    pub(crate) fn ending(&self) -> Option<LineEnding> {
+        self.ending
+    }
+
+    fn count_line_endings(&self, counts: &mut [usize; 4], add: bool) {
+        let mut count = |ending: LineEnding, amount: usize| {
+            if add {
+                counts[ending as usize] += amount;
+            } else {
+                counts[ending as usize] -= amount;
+            }
+        };
+        if let Some(ending) = self.ending {
+            count(ending, 1);
+        }
+        count(LineEnding::Cr, self.ignored_crs as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::{LanguageVersion, LineEndingPolicy, lex_with_options};
+
+    // pieces of GML that are likely to change how the text around them is lexed
+    const PIECES: &[&str] = &[
+        "{",
+        "}",
+        "(",
+        ")",
+        "[",
+        "[@",
+        "[?",
+        "]",
+        "begin",
+        "end",
+        "$\"a{",
+        "}b{",
+        "}c\"",
+        "\"str\"",
+        "\"open",
+        "@\"v\n\"",
+        "@'",
+        "/* c",
+        "*/",
+        "// x",
+        "/// doc",
+        "\n",
+        "\r\n",
+        "\r",
+        "\n\r",
+        "\n\n",
+        " ",
+        "\t",
+        "    ",
+        "#macro X ",
+        "\\",
+        "#region r",
+        "#endregion",
+        "// Feather disable GM2017",
+        "// Feather ignore once GM1041",
+        "x",
+        "1.5",
+        "0x1F",
+        "static",
+        "if",
+        "a = b;",
+        "\u{e9}",
+        "?",
+        "??=",
+        "/",
+        ";",
+    ];
+
+    // xorshift, so that a failure can be reproduced from its seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 16) as usize
+        }
+
+        fn text(&mut self, max_pieces: usize) -> String {
+            let count = self.next() % max_pieces;
+            (0..count)
+                .map(|_| PIECES[self.next() % PIECES.len()])
+                .collect()
+        }
+
+        fn char_boundary(&mut self, text: &str) -> usize {
+            let mut offset = self.next() % (text.len() + 1);
+            while !text.is_char_boundary(offset) {
+                offset -= 1;
+            }
+            offset
+        }
+    }
+
+    fn assert_same(edited: &TokenizedText, lexed: &TokenizedText, context: &str) {
+        assert_eq!(edited.token_count(), lexed.token_count(), "{context}");
+        for index in 0..lexed.token_count() {
+            let token = TokenIndex::from(index);
+            assert_eq!(edited.get_kind(token), lexed.get_kind(token), "{context}");
+            assert_eq!(edited.get_start(token), lexed.get_start(token), "{context}");
+            assert!(
+                edited.tokens.get(token) == lexed.tokens.get(token),
+                "payload of token {index}: {context}"
+            );
+            assert_eq!(
+                edited.trivia_after(token),
+                lexed.trivia_after(token),
+                "{context}"
+            );
+            assert_eq!(
+                edited.matching_delimiter(token),
+                lexed.matching_delimiter(token),
+                "{context}"
+            );
+        }
+
+        assert_eq!(edited.trivia_count(), lexed.trivia_count(), "{context}");
+        for index in 0..lexed.trivia_count() {
+            let (a, b) = (
+                edited.get_trivia(index.into()),
+                lexed.get_trivia(index.into()),
+            );
+            assert_eq!(
+                (a.kind(), a.start(), a.end()),
+                (b.kind(), b.start(), b.end()),
+                "{context}"
+            );
+        }
+
+        assert_eq!(edited.comment_count(), lexed.comment_count(), "{context}");
+        for ((_, a), (_, b)) in edited.comments().zip(lexed.comments()) {
+            assert_eq!(
+                (a.kind(), a.trivia(), a.token(), a.is_leading()),
+                (b.kind(), b.trivia(), b.token(), b.is_leading()),
+                "{context}"
+            );
+        }
+
+        assert_eq!(edited.lines.len(), lexed.lines.len(), "{context}");
+        for index in 0..lexed.lines.len() {
+            let (a, b) = (
+                edited.lines.get(index.into()),
+                lexed.lines.get(index.into()),
+            );
+            assert_eq!(
+                (a.start(), a.indent()),
+                (b.start(), b.indent()),
+                "{context}"
+            );
+        }
+        assert_eq!(
+            edited.last_line_is_inserted, lexed.last_line_is_inserted,
+            "{context}"
+        );
+        assert_eq!(edited.line_ending, lexed.line_ending, "{context}");
+        assert_eq!(
+            edited.has_mixed_line_endings, lexed.has_mixed_line_endings,
+            "{context}"
+        );
+
+        assert_eq!(edited.diagnostics, lexed.diagnostics, "{context}");
+        assert_eq!(
+            edited.has_mismatched_brackets, lexed.has_mismatched_brackets,
+            "{context}"
+        );
+        assert_eq!(edited.suppressions(), lexed.suppressions(), "{context}");
+    }
+
+    fn options(seed: usize) -> LexOptions {
+        const POLICIES: [LineEndingPolicy; 3] = [
+            LineEndingPolicy::Reject,
+            LineEndingPolicy::Accept,
+            LineEndingPolicy::Normalize,
+        ];
+        LexOptions {
+            line_endings: POLICIES[seed % 3],
+            language_version: if seed.is_multiple_of(2) {
+                LanguageVersion::Gms22
+            } else {
+                LanguageVersion::Gms23
+            },
+        }
+    }
+
+    // Each edit is applied, then undone, and both results are compared with lexing the
+    // whole text again.
+    #[test]
+    fn apply_edit_matches_lexing_again() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for iteration in 0..3000 {
+            let options = options(iteration);
+            let text = rng.text(if iteration % 10 == 0 { 2000 } else { 40 });
+            let start = rng.char_boundary(&text);
+            let end = start + rng.char_boundary(&text[start..]);
+            let insert = rng.text(4);
+            let edited_text = format!("{}{}{}", &text[..start], insert, &text[end..]);
+
+            let source = SourceText::from_str(&text);
+            let edited_source = SourceText::from_str(&edited_text);
+            let edit = TextEdit::new(
+                TextRange::new(start.into(), end.into()),
+                insert.len().into(),
+            );
+            let context = format!("{text:?} with {edit:?} gives {edited_text:?}");
+
+            let mut tokens = lex_with_options(&source, &options, None);
+            tokens.apply_edit(&edited_source, edit);
+            let lexed = lex_with_options(&edited_source, &options, None);
+            assert_same(&tokens, &lexed, &context);
+
+            let undo = TextEdit::new(
+                TextRange::new(start.into(), (start + insert.len()).into()),
+                (end - start).into(),
+            );
+            tokens.apply_edit(&source, undo);
+            let lexed = lex_with_options(&source, &options, None);
+            assert_same(&tokens, &lexed, &format!("undoing {context}"));
+        }
+    }
+
+    #[test]
+    fn apply_edit_with_symbols_matches_lexing_again() {
+        let mut rng = Rng(777);
+        for _ in 0..500 {
+            let text = rng.text(40);
+            let offset = rng.char_boundary(&text);
+            let insert = rng.text(4);
+            let edited_text = format!("{}{}{}", &text[..offset], insert, &text[offset..]);
+            let source = SourceText::from_str(&text);
+            let edited_source = SourceText::from_str(&edited_text);
+            let edit = TextEdit::insert(offset.into(), insert.len().into());
+
+            let mut symbols = UserSymbols::new();
+            let mut tokens = crate::lex::lex_with_symbols(&source, &mut symbols);
+            tokens.apply_edit_with_symbols(&edited_source, edit, &mut symbols);
+            let lexed = crate::lex::lex_with_symbols(&edited_source, &mut symbols);
+            assert_same(&tokens, &lexed, &format!("{text:?} with {edit:?}"));
+        }
+    }
+
+    #[test]
+    fn chained_edits_match_lexing_again() {
+        let mut rng = Rng(12345);
+        let mut text = include_str!("../../benches/large_file.gml").to_string();
+        let options = LexOptions::default();
+        let mut tokens = lex_with_options(&SourceText::from_str(&text), &options, None);
+
+        for _ in 0..20 {
+            let start = rng.char_boundary(&text);
+            let end = start + rng.char_boundary(&text[start..(start + 30).min(text.len())]);
+            let insert = rng.text(4);
+            text = format!("{}{}{}", &text[..start], insert, &text[end..]);
+            let edit = TextEdit::new(
+                TextRange::new(start.into(), end.into()),
+                insert.len().into(),
+            );
+
+            let source = SourceText::from_str(&text);
+            tokens.apply_edit(&source, edit);
+            let lexed = lex_with_options(&source, &options, None);
+            assert_same(&tokens, &lexed, &format!("{edit:?} inserting {insert:?}"));
+        }
+    }
+
+    #[test]
+    fn string_escapes_are_reported_once() {
+        let text = SourceText::from_str("s = \"a\\qb\";");
+        let mut tokens = crate::lex::lex(&text);
+        let mut symbols = UserSymbols::new();
+        let token = TokenIndex::from(3);
+        let first = tokens.intern_string_literal(&text, token, &mut symbols);
+        let second = tokens.intern_string_literal(&text, token, &mut symbols);
+
+        assert_eq!(symbols.string_literals.get(first).as_ref(), "a\\qb");
+        assert_eq!(symbols.string_literals.get(second).as_ref(), "a\\qb");
+        let messages: Vec<_> = tokens.diagnostics.iter().map(|d| d.message).collect();
+        assert_eq!(messages, ["invalid escape sequence"]);
+    }
+
+    #[test]
+    fn symbol_ids_start_at_zero() {
+        let text = SourceText::from_str("a = 1;");
+        let mut symbols = UserSymbols::new();
+        let tokens = crate::lex::lex_with_symbols(&text, &mut symbols);
+        assert_eq!(
+            tokens.get_identifier_id(1.into()),
+            Some(IdentifierId::from(0))
+        );
+        assert_eq!(
+            tokens.get_number_literal_id(3.into()),
+            Some(NumberLiteralId::from(0))
+        );
+
+        let tokens = crate::lex::lex(&text);
+        assert_eq!(tokens.get_identifier_id(1.into()), None);
+        assert_eq!(tokens.get_number_literal_id(3.into()), None);
+    }
+
+    // leading trivia, token text and trailing trivia cover every byte of the file
+    #[test]
+    fn tokens_and_trivia_reproduce_the_text() {
+        let text = SourceText::from_str(include_str!("../../benches/large_file.gml"));
+        let tokens = crate::lex::lex(&text);
+        let trivia_text = |trivia: TriviaIndex| {
+            let trivia = tokens.get_trivia(trivia);
+            text.get_slice(trivia.start()..trivia.end())
+        };
+
+        let mut output = Vec::new();
+        for token in 0..tokens.token_count() {
+            let token = TokenIndex::from(token);
+            for trivia in tokens.leading_trivia(token) {
+                output.extend_from_slice(trivia_text(trivia));
+            }
+            output
+                .extend_from_slice(text.get_slice(tokens.get_start(token)..tokens.get_end(token)));
+            for trivia in tokens.trailing_trivia(token) {
+                output.extend_from_slice(trivia_text(trivia));
+            }
+        }
+        assert!(output == text.as_str().as_bytes());
+    }
 }
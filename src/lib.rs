@@ -2,6 +2,7 @@ pub mod chunked_index_vec;
 pub mod fnv;
 pub mod lex;
 pub mod parse;
+mod simd;
 pub mod source_text;
 pub mod typed_index;
-pub mod user_symbols;
\ No newline at end of file
+pub mod user_symbols;
@@ -11,5 +11,9 @@ fn main() {
 
     println!("{}", &parse_result);
 
-    println!("events: {}, tokens: {}", parse_result.events.len(), lex_result.token_count());
+    println!(
+        "events: {}, tokens: {}",
+        parse_result.events.len(),
+        lex_result.token_count()
+    );
 }
@@ -1,2 +1,2 @@
 mod parser;
-pub use parser::*;
\ No newline at end of file
+pub use parser::*;
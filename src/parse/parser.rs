@@ -1,8 +1,38 @@
 use crate::lex::{TokenIndex, TokenKind, TokenizedText};
-use crate::source_text::TextSize;
+use crate::source_text::{TextRange, TextSize};
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
-pub type ParseDiagnostic = &'static str;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Valid code that should still be changed, such as a deprecated spelling.
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    pub message: &'static str,
+    pub range: TextRange,
+    pub severity: Severity,
+}
+
+impl ParseDiagnostic {
+    pub fn new(message: &'static str, start: TextSize, end: TextSize) -> Self {
+        Self {
+            message,
+            range: TextRange::new(start, end),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(message: &'static str, start: TextSize, end: TextSize) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message, start, end)
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum Event {
@@ -55,9 +85,12 @@ enum StateKind {
 #[derive(Debug, Clone, Copy)]
 struct State {
     kind: StateKind,
+    #[allow(dead_code)] // read once recovery reports errors per state
     has_error: bool,
 }
 
+// used once expressions and statements are parsed
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum ConsumeResult {
     Success,
@@ -65,6 +98,7 @@ enum ConsumeResult {
     FailedRecovery,
 }
 
+#[allow(dead_code)]
 impl ConsumeResult {
     pub fn failed(self) -> bool {
         matches!(self, Self::FailedRecovery)
@@ -82,6 +116,7 @@ enum TokenPrecedence {
     WeakBracketClose,
     LeftBrace,
     StrongPunctuator,
+    #[allow(dead_code)] // statement keywords aren't parsed yet
     IntroducerKeyword,
     RightBrace,
 }
@@ -108,9 +143,10 @@ impl From<TokenKind> for TokenPrecedence {
     fn from(kind: TokenKind) -> Self {
         match kind {
             TokenKind::Identifier => TokenPrecedence::IdentifierOrLiteral,
-            TokenKind::RealLiteral | TokenKind::IntegerLiteral => {
-                TokenPrecedence::IdentifierOrLiteral
-            }
+            TokenKind::RealLiteral
+            | TokenKind::IntegerLiteral
+            | TokenKind::HexIntegerLiteral
+            | TokenKind::BinaryLiteral => TokenPrecedence::IdentifierOrLiteral,
             kind if kind.is_prefix_operator()
                 || kind.is_postfix_operator()
                 || kind.is_binary_operator() =>
@@ -167,6 +203,8 @@ pub struct ParseEvents {
     pub diagnostics: Vec<ParseDiagnostic>,
 }
 
+/// Parses `tokens`. Keywords that depend on the language version are decided when the
+/// text is lexed, with `LexOptions::language_version`.
 pub fn parse(tokens: &TokenizedText) -> ParseEvents {
     let mut parser = Parser::new(tokens);
     parser.parse();
@@ -207,7 +245,6 @@ impl<'a> Parser<'a> {
                 }
                 StateKind::EnumItem => self.enum_item(),
                 StateKind::EnumEnd => self.enum_end(),
-                _ => todo!(),
             }
         }
 
@@ -231,6 +268,7 @@ impl<'a> Parser<'a> {
         self.stack.pop().expect("stack underflow")
     }
 
+    #[allow(dead_code)]
     fn current_state(&self) -> &State {
         self.stack.last().expect("stack underflow")
     }
@@ -329,6 +367,7 @@ impl<'a> Parser<'a> {
         self.input.tokens.get(self.cursor).kind()
     }
 
+    #[allow(dead_code)]
     fn peek(&self) -> TokenKind {
         self.input.tokens.get(self.cursor + 1).kind()
     }
@@ -430,8 +469,10 @@ impl<'a> Parser<'a> {
         self.emit_end(); // enum decl
     }
 
+    #[allow(dead_code)]
     fn assign_or_expression(&mut self) {}
 
+    #[allow(dead_code)]
     fn variable_declaration(&mut self) {}
 
     // fn unary_expr(&mut self) -> bool {
@@ -447,7 +488,8 @@ impl<'a> Parser<'a> {
     // }
 
     // only allow postfix operators if we didn't already accept a prefix operator
-    fn primary_expr(&mut self, in_prefix_op: bool) -> bool {
+    #[allow(dead_code)]
+    fn primary_expr(&mut self, _in_prefix_op: bool) -> bool {
         todo!()
     }
 
@@ -479,6 +521,7 @@ impl<'a> Parser<'a> {
     //     true
     // }
 
+    #[allow(dead_code)]
     fn expr(&mut self) -> bool {
         todo!();
     }
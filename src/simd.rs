@@ -0,0 +1,408 @@
+// Helpers shared by the SIMD scanners. Every scanner has a scalar fallback, and the
+// `no-simd` feature forces the fallback on every target. `scalar-scanners` only forces
+// it for the scanners in this file, so that benchmarks can compare them on their own.
+
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+pub(crate) mod neon {
+    use std::arch::aarch64::*;
+
+    // NEON has no movemask, so narrow each byte of a comparison to 4 bits instead
+    #[inline]
+    #[target_feature(enable = "neon")]
+    pub(crate) fn movemask(cmp: uint8x16_t) -> u64 {
+        let narrowed = vshrn_n_u16::<4>(vreinterpretq_u16_u8(cmp));
+        vget_lane_u64::<0>(vreinterpret_u64_u8(narrowed))
+    }
+
+    // the index of the first matching byte in a mask from `movemask`
+    #[inline]
+    pub(crate) fn first_match(mask: u64) -> usize {
+        (mask.trailing_zeros() / 4) as usize
+    }
+}
+
+/// The length of the run of spaces and tabs at the start of `text`.
+pub(crate) fn horizontal_whitespace_len(text: &[u8]) -> usize {
+    // most runs are a single space, which isn't worth a vector load
+    if text.len() < 2 || !is_horizontal_whitespace(text[1]) {
+        return usize::from(text.first().is_some_and(|&c| is_horizontal_whitespace(c)));
+    }
+
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(any(feature = "no-simd", feature = "scalar-scanners"))
+    ))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::horizontal_whitespace_len_avx2(text) };
+        }
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { x86::horizontal_whitespace_len_sse42(text) };
+        }
+    }
+
+    horizontal_whitespace_len_scalar(text, 0)
+}
+
+/// The index of the first `"`, `\` or line feed in `text`, or its length if there is none.
+pub(crate) fn find_string_stop(text: &[u8]) -> usize {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(any(feature = "no-simd", feature = "scalar-scanners"))
+    ))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::find_string_stop_avx2(text) };
+        }
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { x86::find_string_stop_sse42(text) };
+        }
+    }
+
+    find_string_stop_scalar(text, 0)
+}
+
+/// The index of the first `*/` in `text`.
+pub(crate) fn find_comment_end(text: &[u8]) -> Option<usize> {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(any(feature = "no-simd", feature = "scalar-scanners"))
+    ))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::find_comment_end_avx2(text) };
+        }
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { x86::find_comment_end_sse42(text) };
+        }
+    }
+
+    find_comment_end_scalar(text, 0)
+}
+
+const fn is_horizontal_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t')
+}
+
+const fn is_string_stop(c: u8) -> bool {
+    matches!(c, b'"' | b'\\' | b'\n')
+}
+
+fn horizontal_whitespace_len_scalar(text: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < text.len() && is_horizontal_whitespace(text[i]) {
+        i += 1;
+    }
+    i
+}
+
+fn find_string_stop_scalar(text: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < text.len() && !is_string_stop(text[i]) {
+        i += 1;
+    }
+    i
+}
+
+fn find_comment_end_scalar(text: &[u8], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i + 1 < text.len() {
+        if text[i] == b'*' && text[i + 1] == b'/' {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(any(feature = "no-simd", feature = "scalar-scanners"))
+))]
+mod x86 {
+    use super::*;
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    const AVX_CHUNK: usize = 32;
+    const SSE_CHUNK: usize = 16;
+
+    const SSE42_FIND_ANY: i32 = _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_ANY | _SIDD_LEAST_SIGNIFICANT;
+    const SSE42_FIND_NONE: i32 = SSE42_FIND_ANY | _SIDD_NEGATIVE_POLARITY;
+    const SSE42_FIND_SUBSTRING: i32 =
+        _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_ORDERED | _SIDD_LEAST_SIGNIFICANT;
+
+    /// # Safety
+    /// `i + AVX_CHUNK` must not be more than `text.len()`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn load_avx2(text: &[u8], i: usize) -> __m256i {
+        debug_assert!(i + AVX_CHUNK <= text.len());
+        unsafe { _mm256_loadu_si256(text.as_ptr().add(i) as *const __m256i) }
+    }
+
+    /// # Safety
+    /// `i + SSE_CHUNK` must not be more than `text.len()`.
+    #[inline]
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn load_sse(text: &[u8], i: usize) -> __m128i {
+        debug_assert!(i + SSE_CHUNK <= text.len());
+        unsafe { _mm_loadu_si128(text.as_ptr().add(i) as *const __m128i) }
+    }
+
+    // needles for `_mm_cmpestri`, padded to a full vector
+    #[inline]
+    #[target_feature(enable = "sse4.2")]
+    fn needle(bytes: &[u8]) -> __m128i {
+        let mut padded = [0u8; SSE_CHUNK];
+        padded[..bytes.len()].copy_from_slice(bytes);
+        unsafe { _mm_loadu_si128(padded.as_ptr() as *const __m128i) }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn horizontal_whitespace_len_avx2(text: &[u8]) -> usize {
+        let spaces = _mm256_set1_epi8(b' ' as i8);
+        let tabs = _mm256_set1_epi8(b'\t' as i8);
+
+        let mut i = 0;
+        while i + AVX_CHUNK <= text.len() {
+            let chunk = unsafe { load_avx2(text, i) };
+            let is_whitespace = _mm256_or_si256(
+                _mm256_cmpeq_epi8(chunk, spaces),
+                _mm256_cmpeq_epi8(chunk, tabs),
+            );
+            let mask = !(_mm256_movemask_epi8(is_whitespace) as u32);
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += AVX_CHUNK;
+        }
+
+        horizontal_whitespace_len_scalar(text, i)
+    }
+
+    #[target_feature(enable = "sse4.2")]
+    pub(super) fn horizontal_whitespace_len_sse42(text: &[u8]) -> usize {
+        let whitespace = needle(b" \t");
+
+        let mut i = 0;
+        while i + SSE_CHUNK <= text.len() {
+            let chunk = unsafe { load_sse(text, i) };
+            let index = _mm_cmpestri::<SSE42_FIND_NONE>(whitespace, 2, chunk, 16) as usize;
+            if index < SSE_CHUNK {
+                return i + index;
+            }
+            i += SSE_CHUNK;
+        }
+
+        horizontal_whitespace_len_scalar(text, i)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn find_string_stop_avx2(text: &[u8]) -> usize {
+        let quotes = _mm256_set1_epi8(b'"' as i8);
+        let backslashes = _mm256_set1_epi8(b'\\' as i8);
+        let line_feeds = _mm256_set1_epi8(b'\n' as i8);
+
+        let mut i = 0;
+        while i + AVX_CHUNK <= text.len() {
+            let chunk = unsafe { load_avx2(text, i) };
+            let is_stop = _mm256_or_si256(
+                _mm256_or_si256(
+                    _mm256_cmpeq_epi8(chunk, quotes),
+                    _mm256_cmpeq_epi8(chunk, backslashes),
+                ),
+                _mm256_cmpeq_epi8(chunk, line_feeds),
+            );
+            let mask = _mm256_movemask_epi8(is_stop) as u32;
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += AVX_CHUNK;
+        }
+
+        find_string_stop_scalar(text, i)
+    }
+
+    #[target_feature(enable = "sse4.2")]
+    pub(super) fn find_string_stop_sse42(text: &[u8]) -> usize {
+        let stops = needle(b"\"\\\n");
+
+        let mut i = 0;
+        while i + SSE_CHUNK <= text.len() {
+            let chunk = unsafe { load_sse(text, i) };
+            let index = _mm_cmpestri::<SSE42_FIND_ANY>(stops, 3, chunk, 16) as usize;
+            if index < SSE_CHUNK {
+                return i + index;
+            }
+            i += SSE_CHUNK;
+        }
+
+        find_string_stop_scalar(text, i)
+    }
+
+    // compares each byte for `*` and the byte after it for `/`
+    #[target_feature(enable = "avx2")]
+    pub(super) fn find_comment_end_avx2(text: &[u8]) -> Option<usize> {
+        let stars = _mm256_set1_epi8(b'*' as i8);
+        let slashes = _mm256_set1_epi8(b'/' as i8);
+
+        let mut i = 0;
+        while i + AVX_CHUNK < text.len() {
+            // the loop stops a byte early, so that the second load is in bounds too
+            let (chunk, next) = unsafe { (load_avx2(text, i), load_avx2(text, i + 1)) };
+            let is_star = _mm256_cmpeq_epi8(chunk, stars);
+            let is_slash = _mm256_cmpeq_epi8(next, slashes);
+            let mask = _mm256_movemask_epi8(_mm256_and_si256(is_star, is_slash)) as u32;
+            if mask != 0 {
+                return Some(i + mask.trailing_zeros() as usize);
+            }
+            i += AVX_CHUNK;
+        }
+
+        find_comment_end_scalar(text, i)
+    }
+
+    #[target_feature(enable = "sse4.2")]
+    pub(super) fn find_comment_end_sse42(text: &[u8]) -> Option<usize> {
+        let comment_end = needle(b"*/");
+
+        let mut i = 0;
+        while i + SSE_CHUNK <= text.len() {
+            let chunk = unsafe { load_sse(text, i) };
+            let index = _mm_cmpestri::<SSE42_FIND_SUBSTRING>(comment_end, 2, chunk, 16) as usize;
+
+            // a `*` in the last byte is reported as a partial match
+            if index + 1 < SSE_CHUNK {
+                return Some(i + index);
+            }
+            if index + 1 == SSE_CHUNK && text.get(i + SSE_CHUNK) == Some(&b'/') {
+                return Some(i + index);
+            }
+            i += SSE_CHUNK;
+        }
+
+        find_comment_end_scalar(text, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    type Scanner<T> = (&'static str, fn(&[u8]) -> T);
+
+    // the scalar scanner, and each vector scanner that this CPU can run
+    macro_rules! scanners {
+        ($scalar:expr, $sse42:ident, $avx2:ident) => {{
+            // nothing is added without vector scanners
+            #[allow(unused_mut)]
+            let mut scanners: Vec<Scanner<_>> = vec![("scalar", $scalar)];
+            #[cfg(all(
+                any(target_arch = "x86", target_arch = "x86_64"),
+                not(any(feature = "no-simd", feature = "scalar-scanners"))
+            ))]
+            {
+                if is_x86_feature_detected!("sse4.2") {
+                    scanners.push(("sse4.2", |text| unsafe { x86::$sse42(text) }));
+                }
+                if is_x86_feature_detected!("avx2") {
+                    scanners.push(("avx2", |text| unsafe { x86::$avx2(text) }));
+                }
+            }
+            scanners
+        }};
+    }
+
+    fn assert_scanners<T: PartialEq + Debug>(scanners: &[Scanner<T>], text: &[u8], expected: T) {
+        for (name, scan) in scanners {
+            assert_eq!(scan(text), expected, "{name} on {text:?}");
+        }
+    }
+
+    // bytes that aren't stops for any scanner, including ones with the high bit set
+    fn filler(i: usize) -> u8 {
+        [b'a', 0x80, b'z', 0xFF, b'0'][i % 5]
+    }
+
+    #[test]
+    fn horizontal_whitespace_len_matches_scalar() {
+        let scanners = scanners!(
+            |text| horizontal_whitespace_len_scalar(text, 0),
+            horizontal_whitespace_len_sse42,
+            horizontal_whitespace_len_avx2
+        );
+        for len in 0..=70 {
+            for stop in 0..=len {
+                let text: Vec<u8> = (0..len)
+                    .map(|i| match i {
+                        _ if i == stop => filler(i),
+                        _ if i % 3 == 0 => b'\t',
+                        _ => b' ',
+                    })
+                    .collect();
+                assert_scanners(&scanners, &text, stop);
+                assert_eq!(horizontal_whitespace_len(&text), stop, "{text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn find_string_stop_matches_scalar() {
+        let scanners = scanners!(
+            |text| find_string_stop_scalar(text, 0),
+            find_string_stop_sse42,
+            find_string_stop_avx2
+        );
+        for len in 0..=70 {
+            for stop in 0..=len {
+                let text: Vec<u8> = (0..len)
+                    .map(|i| match i {
+                        _ if i == stop => b"\"\\\n"[i % 3],
+                        _ => filler(i),
+                    })
+                    .collect();
+                assert_scanners(&scanners, &text, stop);
+                assert_eq!(find_string_stop(&text), stop, "{text:?}");
+            }
+        }
+    }
+
+    // A `*` that isn't followed by `/` goes at every position before the end, including
+    // the last byte of each chunk, where SSE4.2 reports a partial match.
+    #[test]
+    fn find_comment_end_matches_scalar() {
+        let scanners = scanners!(
+            |text| find_comment_end_scalar(text, 0),
+            find_comment_end_sse42,
+            find_comment_end_avx2
+        );
+        for len in 0..=70 {
+            for end in 0..=len {
+                let mut text: Vec<u8> = (0..len).map(filler).collect();
+                // `end == len` leaves the comment unclosed
+                let expected = (end + 1 < len).then_some(end);
+                if let Some(end) = expected {
+                    text[end..end + 2].copy_from_slice(b"*/");
+                }
+                assert_scanners(&scanners, &text, expected);
+                assert_eq!(find_comment_end(&text), expected, "{text:?}");
+
+                let before = expected.unwrap_or(len);
+                for star in 0..before {
+                    let old = text[star];
+                    text[star] = b'*';
+                    assert_scanners(&scanners, &text, expected);
+                    text[star] = old;
+                }
+            }
+        }
+    }
+}
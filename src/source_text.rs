@@ -1,66 +1,377 @@
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+use crate::simd::neon;
 use crate::typed_index;
+use memmap2::Mmap;
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+use std::arch::aarch64::*;
+#[cfg(all(target_arch = "x86", not(feature = "no-simd")))]
+use std::arch::x86::*;
+#[cfg(all(target_arch = "x86_64", not(feature = "no-simd")))]
 use std::arch::x86_64::*;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::fs;
-use std::hash::{Hash, Hasher};
-use std::ops::Bound;
+use std::io::{self, Read};
+use std::ops::{Bound, Deref};
 use std::path::Path;
 
-pub struct SourceText {
-    buffer: Vec<u8>,
+pub struct SourceText<'a> {
+    buffer: Buffer<'a>,
+    encoding: Encoding,
+}
+
+// The UTF-8 text of a source file, without its byte order mark
+enum Buffer<'a> {
+    Owned(Vec<u8>),
+    Borrowed(&'a [u8]),
+    Mapped { map: Mmap, start: usize },
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Buffer::Owned(bytes) => bytes,
+            Buffer::Borrowed(bytes) => bytes,
+            Buffer::Mapped { map, start } => &map[*start..],
+        }
+    }
+}
+
+/// The encoding of a source file before it was decoded. The text itself is always
+/// stored as UTF-8 without a byte order mark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+impl Encoding {
+    /// Detects the encoding from a byte order mark. Files without one are UTF-8.
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        if bytes.starts_with(UTF8_BOM) {
+            Encoding::Utf8Bom
+        } else if bytes.starts_with(UTF16_LE_BOM) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(UTF16_BE_BOM) {
+            Encoding::Utf16Be
+        } else {
+            Encoding::Utf8
+        }
+    }
+
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[],
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16_LE_BOM,
+            Encoding::Utf16Be => UTF16_BE_BOM,
+        }
+    }
+
+    /// Encodes `text` the way the original file was, including its byte order mark, so
+    /// that a formatted file can be written back.
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        let mut bytes = self.bom().to_vec();
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        }
+        bytes
+    }
+}
+
+/// An error from reading or decoding a source file. Offsets are in bytes from the start
+/// of the file, including any byte order mark.
+#[derive(Debug)]
+pub enum SourceError {
+    Io(io::Error),
+    /// The decoded text doesn't fit in a [`TextSize`].
+    TooLarge {
+        len: usize,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    /// A UTF-16 surrogate without its other half.
+    UnpairedSurrogate {
+        offset: usize,
+    },
+    /// A UTF-16 file that ends in the middle of a code unit.
+    TruncatedUtf16 {
+        offset: usize,
+    },
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::Io(error) => write!(f, "{}", error),
+            SourceError::TooLarge { len } => {
+                write!(f, "file is too large ({} bytes)", len)
+            }
+            SourceError::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 at byte {}", offset)
+            }
+            SourceError::UnpairedSurrogate { offset } => {
+                write!(f, "unpaired UTF-16 surrogate at byte {}", offset)
+            }
+            SourceError::TruncatedUtf16 { offset } => {
+                write!(f, "incomplete UTF-16 code unit at byte {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SourceError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SourceError {
+    fn from(error: io::Error) -> Self {
+        SourceError::Io(error)
+    }
 }
 
 typed_index!(pub struct TextSize(u32));
 
-#[derive(Clone, Copy, Eq, PartialEq)]
-pub struct TextSpan {
-    ptr: *const u8,
-    len: usize,
+/// A half-open range of bytes in a [`SourceText`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextRange {
+    start: TextSize,
+    end: TextSize,
 }
 
-impl TextSpan {
-    pub fn as_slice(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+impl TextRange {
+    pub fn new(start: TextSize, end: TextSize) -> Self {
+        debug_assert!(start <= end);
+        Self { start, end }
     }
-    
-    pub fn to_str(&self) -> &str {
-        unsafe { std::str::from_utf8_unchecked(self.as_slice()) }
+
+    pub fn start(&self) -> TextSize {
+        self.start
     }
+
+    pub fn end(&self) -> TextSize {
+        self.end
+    }
+
+    pub fn len(&self) -> TextSize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// A change to a [`SourceText`]: the bytes in `range` of the old text were replaced by
+/// `new_len` bytes of new text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: TextRange,
+    pub new_len: TextSize,
 }
 
-impl Hash for TextSpan {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write(self.as_slice());
+impl TextEdit {
+    pub fn new(range: TextRange, new_len: TextSize) -> Self {
+        Self { range, new_len }
+    }
+
+    pub fn insert(offset: TextSize, len: TextSize) -> Self {
+        Self::new(TextRange::new(offset, offset), len)
+    }
+
+    pub fn delete(range: TextRange) -> Self {
+        Self::new(range, TextSize::from(0))
+    }
+
+    /// The range of the replacement in the new text.
+    pub fn new_range(&self) -> TextRange {
+        let start = self.range.start();
+        TextRange::new(start, start + self.new_len)
+    }
+
+    /// Maps an offset at or after the end of the edit from the old text to the new text.
+    pub fn shift(&self, old_offset: TextSize) -> TextSize {
+        debug_assert!(old_offset >= self.range.end());
+        self.new_range().end() + (old_offset - self.range.end())
+    }
+
+    /// Maps an offset at or after the end of the edit from the new text to the old text.
+    pub fn unshift(&self, new_offset: TextSize) -> TextSize {
+        debug_assert!(new_offset >= self.new_range().end());
+        self.range.end() + (new_offset - self.new_range().end())
     }
 }
 
-impl Debug for TextSpan {
+/// A piece of a [`SourceText`], borrowed for as long as the text is alive. Spans compare
+/// and hash by content, so spans from different files can be interned together.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextSpan<'src> {
+    text: &'src str,
+}
+
+impl<'src> TextSpan<'src> {
+    pub fn as_slice(&self) -> &'src [u8] {
+        self.text.as_bytes()
+    }
+
+    pub fn to_str(&self) -> &'src str {
+        self.text
+    }
+}
+
+impl Debug for TextSpan<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_str())
+        write!(f, "{}", self.text)
     }
 }
 
-impl SourceText {
-    pub fn from_str(s: &str) -> Self {
-        let buffer = s.as_bytes().to_vec();
-        Self::new(buffer)
+impl<'a> SourceText<'a> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &'a str) -> Self {
+        let (s, encoding) = match s.strip_prefix('\u{feff}') {
+            Some(s) => (s, Encoding::Utf8Bom),
+            None => (s, Encoding::Utf8),
+        };
+        Self::try_new(Buffer::Borrowed(s.as_bytes()), encoding)
+            .unwrap_or_else(|error| panic!("{}", error))
     }
 
+    /// Decodes borrowed bytes without copying them, unless they need to be transcoded.
+    pub fn try_from_slice(bytes: &'a [u8]) -> Result<Self, SourceError> {
+        let (encoding, decoded) = decode(bytes)?;
+        let buffer = match decoded {
+            Decoded::InPlace { start } => Buffer::Borrowed(&bytes[start..]),
+            Decoded::Transcoded(buffer) => Buffer::Owned(buffer),
+        };
+        Self::try_new(buffer, encoding)
+    }
+
+    fn try_new(buffer: Buffer<'a>, encoding: Encoding) -> Result<Self, SourceError> {
+        if buffer.len() >= TextSize::MAX {
+            return Err(SourceError::TooLarge { len: buffer.len() });
+        }
+        Ok(Self { buffer, encoding })
+    }
+}
+
+impl SourceText<'static> {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
-        let buffer = fs::read(path).unwrap();
-        str::from_utf8(&buffer).expect("invalid utf-8");
-        Self::new(buffer)
+        Self::try_from_file(path).unwrap_or_else(|error| panic!("{}", error))
     }
 
-    fn new(buffer: Vec<u8>) -> Self {
-        assert!(buffer.len() < TextSize::MAX);
-        Self { buffer }
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Self, SourceError> {
+        let mut file = fs::File::open(path)?;
+        let (file_len, bom) = Self::checked_file_len(&mut file)?;
+
+        let mut bytes = Vec::with_capacity(file_len);
+        bytes.extend_from_slice(&bom);
+        file.read_to_end(&mut bytes)?;
+        Self::try_from_bytes(bytes)
+    }
+
+    /// Maps a file into memory instead of reading it. UTF-16 files are still read and
+    /// transcoded into an owned buffer.
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated while the text, or anything borrowed
+    /// from it, is alive.
+    pub unsafe fn try_map_file<P: AsRef<Path>>(path: P) -> Result<Self, SourceError> {
+        let mut file = fs::File::open(path)?;
+        Self::checked_file_len(&mut file)?;
+
+        let map = unsafe { Mmap::map(&file)? };
+        let (encoding, decoded) = decode(&map)?;
+        let buffer = match decoded {
+            Decoded::InPlace { start } => Buffer::Mapped { map, start },
+            Decoded::Transcoded(buffer) => Buffer::Owned(buffer),
+        };
+        Self::try_new(buffer, encoding)
+    }
+
+    // Reads the start of the file, which holds its byte order mark if it has one, to check
+    // the length of the decoded text before the rest is read. UTF-16 text can shrink by
+    // half when it's decoded, but no further, and UTF-8 text only loses its byte order
+    // mark. Returns the length of the file and the bytes that were read.
+    fn checked_file_len(file: &mut fs::File) -> Result<(usize, Vec<u8>), SourceError> {
+        let file_len = file.metadata()?.len() as usize;
+        let mut start = Vec::with_capacity(UTF8_BOM.len());
+        file.by_ref()
+            .take(UTF8_BOM.len() as u64)
+            .read_to_end(&mut start)?;
+
+        let encoding = Encoding::detect(&start);
+        let text_len = file_len.saturating_sub(encoding.bom().len());
+        let min_len = match encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be => text_len / 2,
+            Encoding::Utf8 | Encoding::Utf8Bom => text_len,
+        };
+        if min_len >= TextSize::MAX {
+            return Err(SourceError::TooLarge { len: file_len });
+        }
+        Ok((file_len, start))
+    }
+
+    /// Decodes the contents of a source file, stripping any byte order mark and
+    /// transcoding UTF-16 to UTF-8. The original encoding is kept in
+    /// [`SourceText::encoding`].
+    pub fn try_from_bytes(mut bytes: Vec<u8>) -> Result<Self, SourceError> {
+        let (encoding, decoded) = decode(&bytes)?;
+        let buffer = match decoded {
+            Decoded::InPlace { start } => {
+                bytes.drain(..start);
+                bytes
+            }
+            Decoded::Transcoded(buffer) => buffer,
+        };
+        Self::try_new(Buffer::Owned(buffer), encoding)
+    }
+}
+
+impl SourceText<'_> {
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn as_str(&self) -> &str {
+        // the buffer is checked to be UTF-8 when the text is created
+        unsafe { str::from_utf8_unchecked(&self.buffer) }
+    }
+
+    /// The text in its original encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encoding.encode(self.as_str())
     }
 
     pub fn len(&self) -> TextSize {
         TextSize::from(self.buffer.len())
     }
 
+    /// # Safety
+    /// `index` must be less than [`SourceText::len`].
     pub unsafe fn get_byte_unchecked(&self, index: TextSize) -> u8 {
         debug_assert!(index < self.len());
         unsafe { *self.buffer.get_unchecked(usize::from(index)) }
@@ -84,28 +395,104 @@ impl SourceText {
         &self.buffer[start.into()..end.into()]
     }
 
-    pub fn get_span(&self, start: TextSize, end: TextSize) -> TextSpan {
-        let slice = &self.buffer[start.into()..end.into()];
-        TextSpan {
-            ptr: slice.as_ptr(),
-            len: end.into(),
-        }
+    /// # Panics
+    /// If `start` or `end` is not on a character boundary.
+    pub fn get_span(&self, start: TextSize, end: TextSize) -> TextSpan<'_> {
+        let text = self
+            .as_str()
+            .get(usize::from(start)..usize::from(end))
+            .expect("span must start and end on character boundaries");
+        TextSpan { text }
     }
 
     pub fn find_next(&self, byte: u8, start: TextSize) -> Option<TextSize> {
-        let slice = &self.buffer.as_slice()[start.into()..];
-        match index_of(byte, slice) {
-            Some(offset) => Some(start + offset),
-            None => None,
+        let slice = &self.buffer[start.into()..];
+        index_of(byte, slice).map(|offset| start + offset)
+    }
+}
+
+enum Decoded {
+    /// The bytes are UTF-8 text, after a byte order mark of length `start`.
+    InPlace {
+        start: usize,
+    },
+    Transcoded(Vec<u8>),
+}
+
+fn decode(bytes: &[u8]) -> Result<(Encoding, Decoded), SourceError> {
+    let encoding = Encoding::detect(bytes);
+    let bom_len = encoding.bom().len();
+
+    let decoded = match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => {
+            if let Err(error) = str::from_utf8(&bytes[bom_len..]) {
+                return Err(SourceError::InvalidUtf8 {
+                    offset: bom_len + error.valid_up_to(),
+                });
+            }
+            Decoded::InPlace { start: bom_len }
         }
+        Encoding::Utf16Le => Decoded::Transcoded(decode_utf16(
+            &bytes[bom_len..],
+            bom_len,
+            u16::from_le_bytes,
+        )?),
+        Encoding::Utf16Be => Decoded::Transcoded(decode_utf16(
+            &bytes[bom_len..],
+            bom_len,
+            u16::from_be_bytes,
+        )?),
+    };
+    Ok((encoding, decoded))
+}
+
+// `offset` is the position of `bytes` in the file, for error messages
+fn decode_utf16(
+    bytes: &[u8],
+    offset: usize,
+    from_bytes: fn([u8; 2]) -> u16,
+) -> Result<Vec<u8>, SourceError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(SourceError::TruncatedUtf16 {
+            offset: offset + bytes.len() - 1,
+        });
     }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]));
+
+    let mut buffer = Vec::with_capacity(bytes.len());
+    let mut position = offset;
+    for c in char::decode_utf16(units) {
+        match c {
+            Ok(c) => {
+                let mut encoded = [0; 4];
+                buffer.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+                position += c.len_utf16() * 2;
+            }
+            Err(_) => return Err(SourceError::UnpairedSurrogate { offset: position }),
+        }
+    }
+    Ok(buffer)
 }
 
 fn index_of(byte: u8, haystack: &[u8]) -> Option<usize> {
-    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-        if is_x86_feature_detected!("sse2") {
-            return unsafe { index_of_sse2(byte, haystack) };
-        }
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(feature = "no-simd")
+    ))]
+    if is_x86_feature_detected!("sse2") {
+        return unsafe { index_of_sse2(byte, haystack) };
+    }
+
+    #[cfg(all(
+        target_arch = "aarch64",
+        target_endian = "little",
+        not(feature = "no-simd")
+    ))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        return unsafe { index_of_neon(byte, haystack) };
     }
 
     index_of_scalar(byte, haystack)
@@ -122,7 +509,10 @@ fn index_of_scalar(byte: u8, haystack: &[u8]) -> Option<usize> {
 }
 
 #[target_feature(enable = "sse2")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "no-simd")
+))]
 fn index_of_sse2(byte: u8, haystack: &[u8]) -> Option<usize> {
     const SSE_CHUNK: usize = 16;
 
@@ -145,8 +535,156 @@ fn index_of_sse2(byte: u8, haystack: &[u8]) -> Option<usize> {
         i += SSE_CHUNK;
     }
 
-    match index_of_scalar(byte, &haystack[i..]) {
-        Some(offset) => Some(i + offset),
-        None => None,
+    index_of_scalar(byte, &haystack[i..]).map(|offset| i + offset)
+}
+
+#[target_feature(enable = "neon")]
+#[cfg(all(
+    target_arch = "aarch64",
+    target_endian = "little",
+    not(feature = "no-simd")
+))]
+fn index_of_neon(byte: u8, haystack: &[u8]) -> Option<usize> {
+    const NEON_CHUNK: usize = 16;
+
+    let len = haystack.len();
+    let ptr = haystack.as_ptr();
+    let needle_vec = vdupq_n_u8(byte);
+
+    let mut i = 0;
+
+    while i + NEON_CHUNK <= len {
+        let chunk = unsafe { vld1q_u8(ptr.add(i)) };
+        let mask = neon::movemask(vceqq_u8(chunk, needle_vec));
+
+        if mask != 0 {
+            return Some(i + neon::first_match(mask));
+        }
+
+        i += NEON_CHUNK;
+    }
+
+    index_of_scalar(byte, &haystack[i..]).map(|offset| i + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the file is sparse, so nothing is written or read
+    #[test]
+    fn large_utf8_file_is_rejected_before_reading() {
+        let path = std::env::temp_dir().join(format!("gobo-large-{}.gml", std::process::id()));
+        let file = fs::File::create(&path).unwrap();
+        let len = TextSize::MAX as u64 + 1;
+        file.set_len(len).unwrap();
+        drop(file);
+
+        let result = SourceText::try_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(result, Err(SourceError::TooLarge { len: error_len }) if error_len as u64 == len)
+        );
+    }
+
+    #[test]
+    fn utf8_bom_is_stripped() {
+        let bytes = b"\xEF\xBB\xBFx = 1;";
+        let text = SourceText::try_from_slice(bytes).unwrap();
+        assert_eq!(text.encoding(), Encoding::Utf8Bom);
+        assert_eq!(text.as_str(), "x = 1;");
+        assert_eq!(text.encode(), bytes);
+
+        let text = SourceText::from_str("\u{feff}x = 1;");
+        assert_eq!(text.encoding(), Encoding::Utf8Bom);
+        assert_eq!(text.as_str(), "x = 1;");
+    }
+
+    #[test]
+    fn utf16_is_transcoded() {
+        let source = "név = \"😀\";\n";
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let bytes = encoding.encode(source);
+            assert_eq!(&bytes[..2], encoding.bom());
+
+            let text = SourceText::try_from_slice(&bytes).unwrap();
+            assert_eq!(text.encoding(), encoding);
+            assert_eq!(text.as_str(), source);
+            assert_eq!(text.encode(), bytes);
+
+            let text = SourceText::try_from_bytes(bytes.clone()).unwrap();
+            assert_eq!(text.as_str(), source);
+        }
+
+        let bytes = [0xFF, 0xFE, b'a', 0, b'b', 0];
+        assert_eq!(SourceText::try_from_slice(&bytes).unwrap().as_str(), "ab");
+        let bytes = [0xFE, 0xFF, 0, b'a', 0, b'b'];
+        assert_eq!(SourceText::try_from_slice(&bytes).unwrap().as_str(), "ab");
+    }
+
+    // offsets count the byte order mark, so they point into the file as it was read
+    #[test]
+    fn utf16_errors() {
+        let offset = |bytes: &[u8]| match SourceText::try_from_slice(bytes) {
+            Err(SourceError::TruncatedUtf16 { offset }) => ("truncated", offset),
+            Err(SourceError::UnpairedSurrogate { offset }) => ("unpaired", offset),
+            Err(error) => panic!("unexpected error {error}"),
+            Ok(_) => panic!("expected an error for {bytes:?}"),
+        };
+
+        assert_eq!(offset(&[0xFF, 0xFE, b'a', 0, b'b']), ("truncated", 4));
+        assert_eq!(offset(&[0xFE, 0xFF, 0]), ("truncated", 2));
+        // a high surrogate followed by a letter
+        assert_eq!(
+            offset(&[0xFF, 0xFE, b'a', 0, 0x00, 0xD8, b'b', 0]),
+            ("unpaired", 4)
+        );
+        // a low surrogate after a complete pair
+        assert_eq!(
+            offset(&[0xFE, 0xFF, 0xD8, 0x3D, 0xDE, 0x00, 0xDC, 0x00]),
+            ("unpaired", 6)
+        );
+        // a high surrogate at the end of the file
+        assert_eq!(offset(&[0xFE, 0xFF, 0, b'a', 0xD8, 0x00]), ("unpaired", 4));
+    }
+
+    // The needle at every offset of haystacks up to three chunks long, among bytes that
+    // all have the high bit set, and a needle that has it set too. With `no-simd`, this
+    // checks the scalar search alone.
+    #[test]
+    fn index_of_matches_scalar() {
+        let filler = |i: usize| 0x80 | (i * 37 % 0x80) as u8;
+        for needle in [b'\n', 0x80, 0xFF] {
+            for len in 0..=48 {
+                let mut haystack: Vec<u8> = (0..len)
+                    .map(filler)
+                    .map(|b| if b == needle { 0x81 } else { b })
+                    .collect();
+                assert_eq!(index_of_scalar(needle, &haystack), None);
+                assert_eq!(index_of(needle, &haystack), None);
+
+                for offset in 0..len {
+                    let old = haystack[offset];
+                    haystack[offset] = needle;
+                    let context = format!("{needle:#x} at {offset} of {len}");
+                    assert_eq!(
+                        index_of_scalar(needle, &haystack),
+                        Some(offset),
+                        "{context}"
+                    );
+                    assert_eq!(index_of(needle, &haystack), Some(offset), "{context}");
+
+                    #[cfg(all(
+                        any(target_arch = "x86", target_arch = "x86_64"),
+                        not(feature = "no-simd")
+                    ))]
+                    if is_x86_feature_detected!("sse2") {
+                        let found = unsafe { index_of_sse2(needle, &haystack) };
+                        assert_eq!(found, Some(offset), "{context}");
+                    }
+                    haystack[offset] = old;
+                }
+            }
+        }
     }
 }
@@ -5,20 +5,56 @@ use crate::typed_index;
 use crate::typed_index::TypedIndex;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash, Hasher};
+use std::hash::{BuildHasherDefault, DefaultHasher, Hash, Hasher};
 
 typed_index!(pub struct IdentifierId(u32));
 typed_index!(pub struct StringLiteralId(u32));
 typed_index!(pub struct NumberLiteralId(u32));
 
-// Storage for unique values like identifiers and literals used during compilation
-pub struct UserSymbols {
-    pub identifiers: UniqueChunkedIndexVec<TextSpan, IdentifierId, Fnv1aHasher32>,
-    pub string_literals: UniqueChunkedIndexVec<TextSpan, StringLiteralId, Fnv1aHasher32>,
-    pub number_literals: UniqueChunkedIndexVec<TextSpan, NumberLiteralId, Fnv1aHasher32>,
+/// The value of a number literal. Reals compare by their bit pattern so that they can
+/// be interned.
+#[derive(Debug, Clone, Copy)]
+pub enum NumberValue {
+    Integer(i64),
+    Real(f64),
 }
 
-impl UserSymbols {
+impl PartialEq for NumberValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NumberValue::Integer(a), NumberValue::Integer(b)) => a == b,
+            (NumberValue::Real(a), NumberValue::Real(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for NumberValue {}
+
+impl Hash for NumberValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            NumberValue::Integer(value) => {
+                state.write_u8(0);
+                state.write_i64(*value);
+            }
+            NumberValue::Real(value) => {
+                state.write_u8(1);
+                state.write_u64(value.to_bits());
+            }
+        }
+    }
+}
+
+// Storage for unique values like identifiers and literals used during compilation.
+// Identifiers borrow from their source text, which has to outlive the symbols.
+pub struct UserSymbols<'src> {
+    pub identifiers: UniqueChunkedIndexVec<TextSpan<'src>, IdentifierId, Fnv1aHasher32>,
+    pub string_literals: UniqueChunkedIndexVec<Box<str>, StringLiteralId, Fnv1aHasher32>,
+    pub number_literals: UniqueChunkedIndexVec<NumberValue, NumberLiteralId, Fnv1aHasher32>,
+}
+
+impl UserSymbols<'_> {
     pub fn new() -> Self {
         Self {
             identifiers: UniqueChunkedIndexVec::new(),
@@ -28,6 +64,12 @@ impl UserSymbols {
     }
 }
 
+impl Default for UserSymbols<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct UniqueChunkedIndexVec<
     T: Eq + Hash + Clone,
     I: TypedIndex,
@@ -66,6 +108,10 @@ impl<T: Eq + Hash + Clone + Debug, I: TypedIndex + Debug, H: Default + Hasher>
         self.vec.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (I, &T)> {
         self.vec.iter()
     }
@@ -76,3 +122,11 @@ impl<T: Eq + Hash + Clone + Debug, I: TypedIndex + Debug, H: Default + Hasher>
         }
     }
 }
+
+impl<T: Eq + Hash + Clone + Debug, I: TypedIndex + Debug, H: Default + Hasher> Default
+    for UniqueChunkedIndexVec<T, I, H>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}